@@ -3,8 +3,10 @@ use std::fs;
 use backend::{
     data::{ScrapedMainPageEnum, Summer2025MainPage},
     database::Database,
+    embedder::HashEmbedder,
 };
-use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use criterion::{Criterion, criterion_group, criterion_main};
+use std::hint::black_box;
 
 fn bench_database_new(c: &mut Criterion) {
     c.bench_function("Database::new_non_backed", |b| {
@@ -32,7 +34,7 @@ fn bench_add_entry(c: &mut Criterion) {
             .join(" ")
     };
 
-    let db = Database::new_non_backed();
+    let db = Database::new_non_backed().with_embedder(Box::new(HashEmbedder::new(768)));
     let dummy_entry = ScrapedMainPageEnum::Summer2025(Summer2025MainPage {
         url: "https://link".into(),
         main_image: "https://link".into(),
@@ -47,9 +49,10 @@ fn bench_add_entry(c: &mut Criterion) {
         updates: vec![],
     });
 
+    let rt = tokio::runtime::Runtime::new().unwrap();
     c.bench_function("Database::add_entry", |b| {
         b.iter(|| {
-            db.add_entry(black_box(dummy_entry.clone()));
+            rt.block_on(db.add_entry(black_box(dummy_entry.clone())));
         });
     });
 }
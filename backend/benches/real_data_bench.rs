@@ -1,11 +1,11 @@
-use backend::database::Database;
+use backend::{database::Database, paging::Paging, ranking::SearchOptions};
 use criterion::{Criterion, criterion_group, criterion_main};
-use std::{hint::black_box, path::Path};
+use std::hint::black_box;
 
-const POPULAR_15: &'static str = "website game ai portfolio app ai bot for project tracker calculator discord learning python system";
-const NICHE_15: &'static str = "fpga frc cad solder library ftc xrp SPY stock synthesizer sand simulator physics minecraft executable";
-const ENG_15: &'static str = "a and the to for is with of in you that it this your on";
-const FILE_PATH: &'static str = "/Users/ryan/Github/searxing-hc/complete_database.json";
+const POPULAR_15: &str = "website game ai portfolio app ai bot for project tracker calculator discord learning python system";
+const NICHE_15: &str = "fpga frc cad solder library ftc xrp SPY stock synthesizer sand simulator physics minecraft executable";
+const ENG_15: &str = "a and the to for is with of in you that it this your on";
+const FILE_PATH: &str = "/Users/ryan/Github/searxing-hc/complete_database.json";
 fn bench_search(c: &mut Criterion) {
     // todo: relative
     let db = &mut Database::load_file(FILE_PATH);
@@ -23,10 +23,16 @@ fn bench_search(c: &mut Criterion) {
     test_input(c, db, "50_total", total_50);
 }
 
-fn test_input(c: &mut Criterion, db: &mut Database, name: &'static str, input: impl AsRef<str>) {
+fn test_input(c: &mut Criterion, db: &mut Database, name: &str, input: impl AsRef<str>) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
     c.bench_function(name, |b| {
         b.iter(|| {
-            let _ = db.search_and_rank_json(black_box(input.as_ref().to_owned()), 250);
+            rt.block_on(db.search_and_rank_json(
+                black_box(input.as_ref().to_owned()),
+                &Paging::first(250),
+                &SearchOptions::default(),
+            ))
+            .unwrap()
         })
     });
 }
@@ -1,19 +1,20 @@
 use backend::{
-    data::{
-        Journey2025MainPage, ScrapedMainPageEnum, Summer2025IndividualUpdate, Summer2025MainPage,
-    },
+    data::{ScrapedMainPageEnum, Summer2025IndividualUpdate, Summer2025MainPage},
     database::Database,
+    embedder::HashEmbedder,
+    paging::Paging,
+    ranking::SearchOptions,
 };
 use criterion::{Criterion, criterion_group, criterion_main};
-use itertools::Itertools;
-use std::{fs, hint::black_box, path::Path};
+use std::{fs, hint::black_box};
 
-const POPULAR_15: &'static str = "website game ai portfolio app ai bot for project tracker calculator discord learning python system";
-const NICHE_15: &'static str = "fpga frc cad solder library ftc xrp SPY stock synthesizer sand simulator physics minecraft executable";
-const ENG_15: &'static str = "a and the to for is with of in you that it this your on";
+const POPULAR_15: &str = "website game ai portfolio app ai bot for project tracker calculator discord learning python system";
+const NICHE_15: &str = "fpga frc cad solder library ftc xrp SPY stock synthesizer sand simulator physics minecraft executable";
+const ENG_15: &str = "a and the to for is with of in you that it this your on";
 
 fn bench_search(c: &mut Criterion) {
-    let db = &mut Database::new_non_backed();
+    let db = &mut Database::new_non_backed().with_embedder(Box::new(HashEmbedder::new(768)));
+    let rt = tokio::runtime::Runtime::new().unwrap();
 
     let word_list: Vec<String> = fs::read_to_string("../data/word_list.txt")
         .expect("failed to read word list")
@@ -56,7 +57,7 @@ fn bench_search(c: &mut Criterion) {
             demo: None,
             updates: vec![updates; fastrand::usize(0..10)],
         };
-        db.add_entry(ScrapedMainPageEnum::Summer2025((summer_fake)));
+        rt.block_on(db.add_entry(ScrapedMainPageEnum::Summer2025(summer_fake)));
     }
 
     test_input(c, db, "0_blank_query", " ");
@@ -73,10 +74,16 @@ fn bench_search(c: &mut Criterion) {
     test_input(c, db, "50_total", total_50);
 }
 
-fn test_input(c: &mut Criterion, db: &mut Database, name: &'static str, input: impl AsRef<str>) {
+fn test_input(c: &mut Criterion, db: &mut Database, name: &str, input: impl AsRef<str>) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
     c.bench_function(name, |b| {
         b.iter(|| {
-            let _ = db.search_and_rank_json(black_box(input.as_ref().to_owned()), 250);
+            rt.block_on(db.search_and_rank_json(
+                black_box(input.as_ref().to_owned()),
+                &Paging::first(250),
+                &SearchOptions::default(),
+            ))
+            .unwrap()
         })
     });
 }
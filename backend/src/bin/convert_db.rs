@@ -0,0 +1,47 @@
+use backend::{
+    storage::{self, StorageFormat},
+    wal::WriteAheadLog,
+};
+use std::{env, fs, process::exit};
+
+// converts between the json and binary database formats, output format comes
+// from the output file name (.json or anything else for binary)
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: convert_db <input> <output>");
+        exit(1);
+    }
+    let (input, output) = (&args[1], &args[2]);
+
+    // entries since the last save only live in the wal, converting without them would
+    // quietly drop them. the backend folds it in on every save
+    let wal = WriteAheadLog::location(input);
+    if fs::metadata(&wal).is_ok_and(|metadata| metadata.len() > 0) {
+        eprintln!("{wal} has unsaved entries, hit /force-save on the backend first");
+        exit(1);
+    }
+
+    let bytes = fs::read(input).unwrap_or_else(|e| {
+        eprintln!("cant read {input}: {e}");
+        exit(1);
+    });
+    let data = storage::decode(&bytes).unwrap_or_else(|e| {
+        eprintln!("cant decode {input}: {e}");
+        exit(1);
+    });
+
+    let format = StorageFormat::from_path(output);
+    let encoded = storage::encode(&data, format).unwrap();
+    fs::write(output, &encoded).unwrap_or_else(|e| {
+        eprintln!("cant write {output}: {e}");
+        exit(1);
+    });
+    println!(
+        "converted {} entries, {} -> {} bytes ({:?})",
+        data.length,
+        bytes.len(),
+        encoded.len(),
+        format
+    );
+}
@@ -0,0 +1,89 @@
+// bk-tree over 64 bit hashes with hamming distance. children hang off their parent by
+// distance, and by the triangle inequality a lookup only has to follow the ones within
+// radius of the distance to the parent, so most of the tree is never compared
+
+pub fn hamming(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+#[derive(Debug)]
+struct Node<T> {
+    hash: u64,
+    // everything with exactly this hash
+    values: Vec<T>,
+    // (distance to this node, index into nodes)
+    children: Vec<(u32, usize)>,
+}
+
+#[derive(Debug)]
+pub struct BkTree<T> {
+    nodes: Vec<Node<T>>,
+}
+
+impl<T> Default for BkTree<T> {
+    fn default() -> Self {
+        BkTree { nodes: vec![] }
+    }
+}
+
+impl<T> BkTree<T> {
+    pub fn len(&self) -> usize {
+        self.nodes.iter().map(|node| node.values.len()).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    pub fn insert(&mut self, hash: u64, value: T) {
+        let leaf = || Node {
+            hash,
+            values: vec![],
+            children: vec![],
+        };
+        if self.nodes.is_empty() {
+            self.nodes.push(leaf());
+        }
+        let mut at = 0;
+        loop {
+            let distance = hamming(self.nodes[at].hash, hash);
+            if distance == 0 {
+                self.nodes[at].values.push(value);
+                return;
+            }
+            match self.nodes[at]
+                .children
+                .iter()
+                .find(|(child_distance, _)| *child_distance == distance)
+            {
+                Some(&(_, child)) => at = child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(leaf());
+                    self.nodes[at].children.push((distance, child));
+                    at = child;
+                }
+            }
+        }
+    }
+    // everything within radius of hash, unordered
+    pub fn within(&self, hash: u64, radius: u32) -> Vec<(u32, &T)> {
+        let mut found = vec![];
+        if self.nodes.is_empty() {
+            return found;
+        }
+        let mut stack = vec![0];
+        while let Some(at) = stack.pop() {
+            let node = &self.nodes[at];
+            let distance = hamming(node.hash, hash);
+            if distance <= radius {
+                found.extend(node.values.iter().map(|value| (distance, value)));
+            }
+            stack.extend(
+                node.children
+                    .iter()
+                    .filter(|(child_distance, _)| child_distance.abs_diff(distance) <= radius)
+                    .map(|&(_, child)| child),
+            );
+        }
+        found
+    }
+}
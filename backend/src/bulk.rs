@@ -0,0 +1,159 @@
+use serde::Serialize;
+
+use crate::{
+    data::ScrapedMainPageEnum,
+    database::{AddOutcome, Database},
+};
+
+// /bulk_add, newline delimited ScrapedMainPageEnum json. lines are cut out of the body
+// as it arrives and handed to add_entries in small batches, so the write lock is only
+// held for one batch at a time and searches keep going during a big import
+
+pub const BATCH_SIZE: usize = 32;
+// a single project is a few kb, anything this long is a broken upload
+pub const MAX_LINE: usize = 4 * 1024 * 1024;
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum LineResult {
+    Added {
+        line: usize,
+        #[serde(flatten)]
+        outcome: AddOutcome,
+    },
+    Rejected {
+        line: usize,
+        status: &'static str,
+        reason: String,
+    },
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct BulkSummary {
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub rejected: usize,
+    // stored without a fresh embedding, see AddOutcome
+    pub pending: usize,
+    pub results: Vec<LineResult>,
+}
+
+impl LineResult {
+    pub fn line(&self) -> usize {
+        match self {
+            LineResult::Added { line, .. } | LineResult::Rejected { line, .. } => *line,
+        }
+    }
+}
+
+impl BulkSummary {
+    fn added(&mut self, line: usize, outcome: AddOutcome) {
+        match outcome {
+            AddOutcome::Inserted { pending, .. } => {
+                self.inserted += 1;
+                self.pending += pending as usize;
+            }
+            AddOutcome::Updated { pending, .. } => {
+                self.updated += 1;
+                self.pending += pending as usize;
+            }
+            AddOutcome::Unchanged { .. } => self.unchanged += 1,
+        }
+        self.results.push(LineResult::Added { line, outcome });
+    }
+    fn rejected(&mut self, line: usize, reason: String) {
+        self.rejected += 1;
+        self.results.push(LineResult::Rejected {
+            line,
+            status: "rejected",
+            reason,
+        });
+    }
+}
+
+pub struct BulkImport<'a> {
+    database: &'a Database,
+    buffer: Vec<u8>,
+    // 1 based like an editor, blank lines still count
+    line: usize,
+    // set while throwing away the rest of a line that went over MAX_LINE
+    overlong: bool,
+    pending: Vec<(usize, ScrapedMainPageEnum)>,
+    summary: BulkSummary,
+}
+
+impl<'a> BulkImport<'a> {
+    pub fn new(database: &'a Database) -> BulkImport<'a> {
+        BulkImport {
+            database,
+            buffer: vec![],
+            line: 0,
+            overlong: false,
+            pending: Vec::with_capacity(BATCH_SIZE),
+            summary: BulkSummary::default(),
+        }
+    }
+    // chunks can split lines anywhere, including inside a utf8 character
+    pub async fn push(&mut self, mut chunk: &[u8]) {
+        while let Some(newline) = chunk.iter().position(|b| *b == b'\n') {
+            self.extend(&chunk[..newline]);
+            self.end_line();
+            chunk = &chunk[newline + 1..];
+            if self.pending.len() >= BATCH_SIZE {
+                self.flush().await;
+            }
+        }
+        self.extend(chunk);
+    }
+    pub async fn finish(mut self) -> BulkSummary {
+        // last line without a trailing newline
+        if !self.buffer.is_empty() || self.overlong {
+            self.end_line();
+        }
+        self.flush().await;
+        // bad json is reported right away, everything else once its batch is done
+        self.summary.results.sort_by_key(LineResult::line);
+        self.summary
+    }
+    fn extend(&mut self, bytes: &[u8]) {
+        if self.overlong {
+            return;
+        }
+        if self.buffer.len() + bytes.len() > MAX_LINE {
+            self.overlong = true;
+            self.buffer = vec![];
+            return;
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
+    fn end_line(&mut self) {
+        self.line += 1;
+        let bytes = std::mem::take(&mut self.buffer);
+        if std::mem::take(&mut self.overlong) {
+            self.summary
+                .rejected(self.line, format!("line is over {MAX_LINE} bytes"));
+            return;
+        }
+        if bytes.trim_ascii().is_empty() {
+            return;
+        }
+        match serde_json::from_slice(&bytes) {
+            Ok(entry) => self.pending.push((self.line, entry)),
+            Err(e) => self
+                .summary
+                .rejected(self.line, format!("invalid json: {e}")),
+        }
+    }
+    async fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let (lines, entries): (Vec<usize>, Vec<ScrapedMainPageEnum>) =
+            std::mem::take(&mut self.pending).into_iter().unzip();
+        let outcomes = self.database.add_entries(entries).await;
+        for (line, outcome) in lines.into_iter().zip(outcomes) {
+            self.summary.added(line, outcome);
+        }
+    }
+}
@@ -1,6 +1,12 @@
 use enum_dispatch::enum_dispatch;
 use serde::{Deserialize, Serialize};
 use serde_big_array::BigArray;
+use std::iter;
+
+use crate::{embedder, lexical, query};
+
+// lowercase event names for event: filters, one per ScrapedMainPageEnum variant
+pub const EVENTS: &[&str] = &["journey2025", "summer2025"];
 
 #[derive(Serialize, Debug)]
 pub struct DetailedSearchResult {
@@ -11,32 +17,181 @@ pub struct DetailedSearchResult {
     pub page: GenericPreviewSearchData,
 }
 
+// what /query sends back, one page of results
+#[derive(Serialize, Debug)]
+pub struct SearchResultsPage {
+    pub results: Vec<DetailedSearchResult>,
+    // exact for keyword only searches, an upper bound once embeddings are involved
+    pub total_estimate: usize,
+    pub offset: usize,
+    // pass back as ?cursor= for the next page, null on the last one
+    pub next_cursor: Option<String>,
+    // the embedder couldnt be reached, ranked by keywords only (and DatabasePage::rank
+    // for queries without any)
+    pub degraded: bool,
+}
+
+// what /preview sends back, everything stored about one project
+#[derive(Serialize, Debug)]
+pub struct ProjectDetails {
+    pub id: usize,
+    pub key: UniqueString,
+    pub page: ScrapedMainPageEnum,
+    // null until the entry has been embedded
+    pub scores: Option<ComputedScores>,
+    pub revisions: usize,
+}
+
+// one line of /admin/pending, an entry the embedder still owes a vector
+#[derive(Serialize, Debug)]
+pub struct PendingEntry {
+    pub id: usize,
+    pub key: UniqueString,
+    pub name: String,
+    // has an embedding, just of an older version of the text
+    pub outdated: bool,
+    // when the current version came in
+    pub scraped_at: u64,
+}
+
+// one line of /admin/similar_images, a project with an image that looks like the one
+// being checked
+#[derive(Serialize, Debug)]
+pub struct SimilarProject {
+    pub id: usize,
+    pub key: UniqueString,
+    pub name: String,
+    // bits that differ between the two dhashes out of 64, 0 is the same picture
+    pub distance: u32,
+    // their archived copy and where it was linked from
+    pub image: String,
+    pub source: String,
+}
+
+// one side of a /duplicates pair
+#[derive(Serialize, Debug)]
+pub struct ProjectRef {
+    pub id: usize,
+    pub key: UniqueString,
+    pub name: String,
+    pub event: &'static str,
+    pub author: String,
+}
+
+// fraud::DuplicatePair with both projects filled in for reviewers
+#[derive(Serialize, Debug)]
+pub struct DuplicateView {
+    pub a: ProjectRef,
+    pub b: ProjectRef,
+    pub cosine: Option<f32>,
+    pub jaccard: Option<f32>,
+    pub same_event: bool,
+    pub same_author: bool,
+    pub score: f32,
+}
+
+// ComputedData minus the embedding, nobody wants 768 floats in a detail view
+#[derive(Serialize, Debug)]
+pub struct ComputedScores {
+    pub ai_description: f32,
+    pub ai_code: f32,
+    pub score_multiplier: f32,
+    pub model: String,
+}
+
 #[derive(Serialize, Debug)]
 pub struct GenericPreviewSearchData {
     pub img: String,
+    // small version of img for result grids, null until the media archive has one
+    pub thumb: Option<String>,
     pub name: String,
     pub description: String,
     pub props: String,
 }
 
+// the structured bits the query language filters on, see query.rs
+#[derive(Debug)]
+pub struct FilterFields<'a> {
+    pub event: &'static str,
+    pub author: &'a str,
+    pub followers: u32,
+    // journey only
+    pub stonks: Option<u32>,
+    pub time_seconds: u64,
+    pub updates: usize,
+    pub has_repo: bool,
+    pub has_demo: bool,
+    pub has_readme: bool,
+    pub has_image: bool,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ComputedData {
     #[serde(with = "BigArray")]
     pub embedding: [f32; 768],
     pub ai_description: f32,
     pub ai_code: f32,
+    // set through /set_extras, scales the fused search score, 1.0 leaves it alone
+    #[serde(default = "default_multiplier")]
+    pub score_multiplier: f32,
+    // embedder model that made `embedding`, vectors from two models cant be compared
+    #[serde(default = "legacy_model")]
+    pub model: String,
+    // embedding is of an older version of the text, the embedder was down when it
+    // changed. still searchable, the retry worker swaps it out
+    #[serde(default)]
+    pub outdated: bool,
+}
+fn default_multiplier() -> f32 {
+    1.0
+}
+fn legacy_model() -> String {
+    embedder::LEGACY_MODEL.to_owned()
 }
-#[derive(Eq, Hash, PartialEq)]
+impl ComputedData {
+    // freshly embedded, nothing from the offline jobs yet
+    pub fn new(embedding: [f32; 768], model: &str) -> ComputedData {
+        ComputedData {
+            embedding,
+            ai_description: 0.0,
+            ai_code: 0.0,
+            score_multiplier: 1.0,
+            model: model.to_owned(),
+            outdated: false,
+        }
+    }
+    pub fn scores(&self) -> ComputedScores {
+        ComputedScores {
+            ai_description: self.ai_description,
+            ai_code: self.ai_code,
+            score_multiplier: self.score_multiplier,
+            model: self.model.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Eq, Hash, PartialEq, Debug, Clone)]
 pub struct UniqueString(pub String);
 
 #[enum_dispatch]
 pub trait DatabasePage {
     fn preview(&self) -> GenericPreviewSearchData;
     fn unique_string(&self) -> UniqueString;
-    fn rank(&self, query: &String, extra: &Option<ComputedData>) -> f32;
+    fn rank(&self, query: &str, extra: &Option<ComputedData>) -> f32;
+    // (weight, text) pairs that go into the keyword index
+    fn lexical_fields(&self) -> Vec<(f32, &str)>;
+    // what gets sent to the embedder, re-embedding only happens when this changes
+    fn embedding_text(&self) -> String;
+    fn filter_fields(&self) -> FilterFields<'_>;
+    // description and devlog messages, what fraud.rs compares for copied text
+    fn written_text(&self) -> Vec<&str>;
+    // links to images and attachments, what the media archive keeps copies of
+    fn media(&self) -> Vec<&str>;
+    // same links in the same order, for pointing them at the archived copies
+    fn media_mut(&mut self) -> Vec<&mut String>;
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[enum_dispatch(DatabasePage)]
 pub enum ScrapedMainPageEnum {
     Journey2025(Journey2025MainPage),
@@ -44,7 +199,7 @@ pub enum ScrapedMainPageEnum {
 }
 
 // Journey 2025
-#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
 pub struct Journey2025MainPage {
     pub id: u32,
     pub main_image: String,
@@ -59,26 +214,96 @@ pub struct Journey2025MainPage {
     pub demo: Option<String>,
     pub updates: Vec<Journey2025IndividualUpdate>,
 }
-#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
 pub struct Journey2025IndividualUpdate {
-    time: String,
-    message: String,
-    attatchments: Vec<String>,
+    pub time: String,
+    pub message: String,
+    pub attatchments: Vec<String>,
 }
 impl DatabasePage for Journey2025MainPage {
     fn preview(&self) -> GenericPreviewSearchData {
-        todo!()
+        GenericPreviewSearchData {
+            img: self.main_image.clone(),
+            thumb: None,
+            name: self.name.clone(),
+            description: self.description.clone(),
+            props: format!("updates: {}", self.updates.len()),
+        }
     }
+    // just the id, stored history, wal records and extras are all keyed by it
     fn unique_string(&self) -> UniqueString {
         UniqueString(format!("{}", self.id))
     }
-    fn rank(&self, query: &String, extra: &Option<ComputedData>) -> f32 {
-        self.followers as f32 + self.stonks as f32 * 0.2
+    fn rank(&self, query: &str, _extra: &Option<ComputedData>) -> f32 {
+        let terms: Vec<String> = lexical::tokenize(query).collect();
+        let hits = lexical::tokenize(&self.name)
+            .chain(lexical::tokenize(&self.description))
+            .filter(|token| terms.contains(token))
+            .count();
+        hits as f32 + self.followers as f32 + self.stonks as f32 * 0.2
+    }
+    fn lexical_fields(&self) -> Vec<(f32, &str)> {
+        let mut fields = vec![(3.0, self.name.as_str()), (1.0, self.description.as_str())];
+        if let Some(readme) = &self.readme {
+            fields.push((0.5, readme));
+        }
+        for update in &self.updates {
+            fields.push((1.0, &update.message));
+        }
+        fields
+    }
+    // some journey projects never got a description
+    fn embedding_text(&self) -> String {
+        if self.description.trim().is_empty() {
+            self.name.clone()
+        } else {
+            self.description.clone()
+        }
+    }
+    fn filter_fields(&self) -> FilterFields<'_> {
+        FilterFields {
+            event: "journey2025",
+            author: &self.author,
+            followers: self.followers as u32,
+            stonks: Some(self.stonks as u32),
+            time_seconds: self.time_seconds(),
+            updates: self.updates.len(),
+            has_repo: self.repo.is_some(),
+            has_demo: self.demo.is_some(),
+            has_readme: self.readme.is_some(),
+            has_image: !self.main_image.is_empty(),
+        }
+    }
+    fn written_text(&self) -> Vec<&str> {
+        iter::once(self.description.as_str())
+            .chain(self.updates.iter().map(|update| update.message.as_str()))
+            .collect()
+    }
+    fn media(&self) -> Vec<&str> {
+        iter::once(&self.main_image)
+            .chain(self.updates.iter().flat_map(|update| &update.attatchments))
+            .map(String::as_str)
+            .collect()
+    }
+    fn media_mut(&mut self) -> Vec<&mut String> {
+        iter::once(&mut self.main_image)
+            .chain(
+                self.updates
+                    .iter_mut()
+                    .flat_map(|update| update.attatchments.iter_mut()),
+            )
+            .collect()
+    }
+}
+impl Journey2025MainPage {
+    // time is kept as the text journey showed, 0 if it cant be read
+    pub fn time_seconds(&self) -> u64 {
+        query::parse_duration(&self.time).unwrap_or(0)
     }
 }
 
 // Summer of Making 2025
-#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
 pub struct Summer2025MainPage {
     pub url: String,
     pub main_image: String,
@@ -93,7 +318,7 @@ pub struct Summer2025MainPage {
     pub updates: Vec<Summer2025IndividualUpdate>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct Summer2025IndividualUpdate {
     pub time: u32,
     pub message: String,
@@ -103,6 +328,7 @@ impl DatabasePage for Summer2025MainPage {
     fn preview(&self) -> GenericPreviewSearchData {
         GenericPreviewSearchData {
             img: self.main_image.clone(),
+            thumb: None,
             name: self.name.clone(),
             description: self.description.clone(),
             props: format!("updates: {}", self.updates.len()),
@@ -111,7 +337,7 @@ impl DatabasePage for Summer2025MainPage {
     fn unique_string(&self) -> UniqueString {
         UniqueString(self.url.clone())
     }
-    fn rank(&self, query: &String, extra: &Option<ComputedData>) -> f32 {
+    fn rank(&self, query: &str, _extra: &Option<ComputedData>) -> f32 {
         // let mut acc = 0.0;
         // if let Some(val) = std::hint::black_box(extra) {
         //     for i in 0..768 {
@@ -120,11 +346,63 @@ impl DatabasePage for Summer2025MainPage {
         //     return  acc;
         // }
         // 0.0
-        return self
-            .description
-            .split_ascii_whitespace()
-            .filter(|x| x == query)
-            .count() as f32
-            + self.time as f32;
+        // same as journey, time is in seconds and would drown out the hits
+        let terms: Vec<String> = lexical::tokenize(query).collect();
+        let hits = lexical::tokenize(&self.name)
+            .chain(lexical::tokenize(&self.description))
+            .filter(|token| terms.contains(token))
+            .count();
+        hits as f32 + self.followers as f32
+    }
+    fn lexical_fields(&self) -> Vec<(f32, &str)> {
+        let mut fields = vec![(3.0, self.name.as_str()), (1.0, self.description.as_str())];
+        if let Some(readme) = &self.readme {
+            fields.push((0.5, readme));
+        }
+        for update in &self.updates {
+            fields.push((1.0, &update.message));
+        }
+        fields
+    }
+    fn embedding_text(&self) -> String {
+        self.description.clone()
+    }
+    fn filter_fields(&self) -> FilterFields<'_> {
+        FilterFields {
+            event: "summer2025",
+            author: &self.author,
+            followers: self.followers as u32,
+            stonks: None,
+            time_seconds: self.time as u64,
+            updates: self.updates.len(),
+            has_repo: self.repo.is_some(),
+            has_demo: self.demo.is_some(),
+            has_readme: self.readme.is_some(),
+            has_image: !self.main_image.is_empty(),
+        }
+    }
+    fn written_text(&self) -> Vec<&str> {
+        iter::once(self.description.as_str())
+            .chain(self.updates.iter().map(|update| update.message.as_str()))
+            .collect()
+    }
+    fn media(&self) -> Vec<&str> {
+        iter::once(&self.main_image)
+            .chain(
+                self.updates
+                    .iter()
+                    .filter_map(|update| update.image.as_ref()),
+            )
+            .map(String::as_str)
+            .collect()
+    }
+    fn media_mut(&mut self) -> Vec<&mut String> {
+        iter::once(&mut self.main_image)
+            .chain(
+                self.updates
+                    .iter_mut()
+                    .filter_map(|update| update.image.as_mut()),
+            )
+            .collect()
     }
 }
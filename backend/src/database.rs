@@ -3,15 +3,33 @@ use ordered_float::OrderedFloat;
 use serde::{self, Deserialize, Serialize};
 use std::{
     cmp::Reverse,
-    collections::{BinaryHeap, HashMap},
+    collections::{BTreeMap, BinaryHeap, HashMap},
     fs::{self, File},
-    io::Write,
+    io,
+    path::Path,
     sync::{Mutex, RwLock},
 };
 
 use crate::{
-    data::{ComputedData, DatabasePage, DetailedSearchResult, ScrapedMainPageEnum, UniqueString},
-    embedder::OllamaEmbedder,
+    data::{
+        ComputedData, DatabasePage, DetailedSearchResult, DuplicateView, PendingEntry,
+        ProjectDetails, ProjectRef, ScrapedMainPageEnum, SearchResultsPage, SimilarProject,
+        UniqueString,
+    },
+    embedder::{self, EmbedError, Embedder},
+    fraud::DuplicatePair,
+    history::{self, Revision, RevisionDiff},
+    hnsw::HnswIndex,
+    lexical::InvertedIndex,
+    media::MediaArchive,
+    paging::{self, Paging},
+    quantize::{self, Quantization, QuantizedVectors},
+    query::{self, QueryError},
+    query_cache::QueryCache,
+    ranking::{self, SearchOptions},
+    reembed::Reembedded,
+    storage::{self, StorageFormat},
+    wal::{PendingSync, WalRecord, WriteAheadLog},
 };
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -19,14 +37,210 @@ pub struct UnderlyingData {
     pub raw_text: Vec<ScrapedMainPageEnum>,
     pub processed: Vec<Option<ComputedData>>,
     pub length: usize,
+    // every earlier version of every project, oldest first. the current one is raw_text,
+    // keeping it here too would store every page twice
+    #[serde(default)]
+    pub history: HashMap<UniqueString, Vec<Revision>>,
+    // when each raw_text entry was scraped, 0 for ones from before history was kept
+    #[serde(default)]
+    pub scraped_at: Vec<u64>,
     #[serde(skip)]
     pub arena_allocator: Mutex<Bump>,
+    // rebuilt from raw_text on load, not worth storing
+    #[serde(skip)]
+    pub lexical: InvertedIndex,
+    // saved next to the main file, see hnsw_location
+    #[serde(skip)]
+    pub vectors: HnswIndex,
+    // int8 and sign bit copies of processed for the first pass of a search, empty unless
+    // SOM_BACKEND_QUANTIZE is set
+    #[serde(skip)]
+    pub quantized: QuantizedVectors,
+    // unique string -> index, lives under the same lock as the data it points into
+    #[serde(skip)]
+    pub relational: HashMap<UniqueString, usize>,
+}
+
+// partial update from /set_extras, None leaves that part as it is
+#[derive(Debug, Default, Clone)]
+pub struct Extras {
+    pub ai_description: Option<f32>,
+    pub ai_code: Option<f32>,
+    pub score_multiplier: Option<f32>,
+    pub embedding: Option<Vec<f32>>,
+    // what made `embedding`, taken to be the current embedder when left out
+    pub model: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExtrasError {
+    NotFound,
+    // nothing to attach scores to until the embedder has seen it or an embedding is sent
+    NotEmbedded,
+    WrongDimensions { got: usize },
+    BadValue(&'static str),
+}
+
+impl std::fmt::Display for ExtrasError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtrasError::NotFound => write!(f, "ID not found"),
+            ExtrasError::NotEmbedded => {
+                write!(
+                    f,
+                    "entry has no computed data yet, send an embedding with it"
+                )
+            }
+            ExtrasError::WrongDimensions { got } => {
+                write!(
+                    f,
+                    "embedding has {got} dimensions, expected {EMBEDDING_DIMS}"
+                )
+            }
+            ExtrasError::BadValue(field) => write!(f, "{field} has to be a finite number"),
+        }
+    }
+}
+
+// pending means the embedder was down, the entry is stored and searchable by keyword
+// and the retry worker embeds it once the embedder is back
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum AddOutcome {
+    Inserted {
+        id: usize,
+        pending: bool,
+    },
+    Updated {
+        id: usize,
+        reembedded: bool,
+        pending: bool,
+    },
+    Unchanged {
+        id: usize,
+    },
+}
+
+// below this a linear scan is faster than walking the graph and always exact
+const BRUTE_FORCE_LIMIT: usize = 2048;
+const EMBEDDING_DIMS: usize = 768;
+
+impl UnderlyingData {
+    // only for entries that have been embedded
+    pub fn embedding(&self, index: usize) -> &[f32] {
+        &self.processed[index].as_ref().unwrap().embedding
+    }
+    fn index_vector(&mut self, index: usize) {
+        self.index_graph(index);
+        match &self.processed[index] {
+            Some(computed) => self.quantized.push(&computed.embedding),
+            // keeps positions lined up, brute force never looks at entries without a vector
+            None => self.quantized.push(&[0.0; EMBEDDING_DIMS]),
+        }
+    }
+    fn index_graph(&mut self, index: usize) {
+        if self.processed[index].is_none() {
+            self.vectors.insert_unlinked(index);
+            return;
+        }
+        let processed = &self.processed;
+        self.vectors
+            .insert(index, |i| &processed[i].as_ref().unwrap().embedding);
+    }
+    // updates every per entry structure, the wal is up to the caller
+    fn upsert_entry(
+        &mut self,
+        page: ScrapedMainPageEnum,
+        computed: Option<ComputedData>,
+        scraped_at: u64,
+    ) -> usize {
+        let key = page.unique_string();
+        let Some(&index) = self.relational.get(&key) else {
+            let index = self.push_entry(page, computed);
+            self.scraped_at.push(scraped_at);
+            return index;
+        };
+        // a wal replay of an add that made it into the snapshot and was then edited again
+        // (the crash came between saving and truncating the wal), the snapshot is newer
+        if self.history.get(&key).is_some_and(|chain| {
+            chain
+                .iter()
+                .any(|revision| revision.scraped_at == scraped_at && revision.page == page)
+        }) {
+            return index;
+        }
+        // otherwise a rescrape that found nothing new keeps its chain as it is
+        if self.raw_text[index] != page {
+            let previous = Revision {
+                scraped_at: self.scraped_at[index],
+                page: self.raw_text[index].clone(),
+            };
+            self.history.entry(key).or_default().push(previous);
+            self.scraped_at[index] = scraped_at;
+        }
+        self.replace_entry(index, page, computed);
+        index
+    }
+    // every version of a project oldest first, the last one is the current page
+    fn revisions(&self, key: &UniqueString) -> Option<Vec<Revision>> {
+        let &index = self.relational.get(key)?;
+        let mut revisions = self.history.get(key).cloned().unwrap_or_default();
+        revisions.push(Revision {
+            scraped_at: self.scraped_at[index],
+            page: self.raw_text[index].clone(),
+        });
+        Some(revisions)
+    }
+    fn push_entry(&mut self, page: ScrapedMainPageEnum, computed: Option<ComputedData>) -> usize {
+        let index = self.length;
+        self.relational.insert(page.unique_string(), index);
+        self.lexical.add_document(index, &page.lexical_fields());
+        self.raw_text.push(page);
+        self.processed.push(computed);
+        self.length += 1;
+        self.index_vector(index);
+        index
+    }
+    fn replace_entry(
+        &mut self,
+        index: usize,
+        page: ScrapedMainPageEnum,
+        computed: Option<ComputedData>,
+    ) {
+        let old_page = std::mem::replace(&mut self.raw_text[index], page);
+        self.lexical.replace_document(
+            index,
+            &old_page.lexical_fields(),
+            &self.raw_text[index].lexical_fields(),
+        );
+
+        self.set_computed(index, computed);
+    }
+    // only touches the vector indexes when the embedding itself changed
+    fn set_computed(&mut self, index: usize, computed: Option<ComputedData>) {
+        let old_embedding = self.processed[index].as_ref().map(|c| c.embedding);
+        self.processed[index] = computed;
+        let new_embedding = self.processed[index].as_ref().map(|c| c.embedding);
+        // nothing ever goes from having a vector back to none, see add_entries
+        if old_embedding != new_embedding && new_embedding.is_some() {
+            let processed = &self.processed;
+            self.quantized
+                .set(index, &processed[index].as_ref().unwrap().embedding);
+            self.vectors
+                .update(index, |i| &processed[i].as_ref().unwrap().embedding);
+        }
+    }
 }
 pub struct Database {
     pub raw_data: RwLock<UnderlyingData>,
-    pub relational: HashMap<UniqueString, usize>,
     pub file_location: &'static str,
-    pub ollama: OllamaEmbedder,
+    pub embedder: Box<dyn Embedder>,
+    // embeddings of recent /query texts, only valid for this embedder
+    pub query_cache: QueryCache,
+    // only appended to while holding the raw_data write lock so it lines up with saves
+    pub wal: Mutex<WriteAheadLog>,
+    // archived copies of linked images, search results and details point at them
+    pub media: MediaArchive,
 }
 
 impl Database {
@@ -36,99 +250,561 @@ impl Database {
                 raw_text: vec![],
                 processed: vec![],
                 length: 0,
+                history: HashMap::new(),
+                scraped_at: vec![],
                 arena_allocator: Mutex::new(Bump::new()),
+                lexical: InvertedIndex::new(),
+                vectors: HnswIndex::default(),
+                quantized: QuantizedVectors::from_env(EMBEDDING_DIMS),
+                relational: HashMap::new(),
             }),
-            relational: HashMap::new(),
             file_location: "",
-            ollama: OllamaEmbedder::new(),
+            embedder: embedder::from_env(EMBEDDING_DIMS),
+            query_cache: QueryCache::from_env(),
+            wal: Mutex::new(WriteAheadLog::disabled()),
+            media: MediaArchive::disabled(),
         }
     }
     pub fn load_file(name: &'static str) -> Database {
-        let raw_data_from_file = match fs::read_to_string(name) {
-            Ok(data) => match serde_json::from_str(&data) {
-                Ok(good) => good,
-                Err(e) => {
-                    eprintln!("cant load from json");
-                    None
-                }
+        let mut from_legacy = false;
+        let raw_data_from_file = match fs::read(name) {
+            Ok(bytes) if !bytes.is_empty() => match storage::decode(&bytes) {
+                Ok(good) => Some(good),
+                // starting empty would overwrite the whole archive on the next save
+                Err(e) => panic!("cant load database {name}: {e}"),
             },
-            Err(e) => {
-                eprintln!("cant load file");
-                if let Err(f) = File::create(name) {
-                    eprintln!("cant make new file (bad)");
+            // missing, or a fresh file from a previous first run
+            result => {
+                if let Err(e) = result {
+                    eprintln!("cant load file: {e}");
+                    if let Err(f) = File::create(name) {
+                        eprintln!("cant make new file (bad): {f}");
+                    }
                 }
-                None
+                let legacy = Self::load_legacy(name);
+                from_legacy = legacy.is_some();
+                legacy
             }
         };
         // why tf cant type be infered, lsp knows but not rustc
-        let raw_data: UnderlyingData = raw_data_from_file.unwrap_or_default();
+        let mut raw_data: UnderlyingData = raw_data_from_file.unwrap_or_default();
 
         assert!(raw_data.length == raw_data.processed.len());
         assert!(raw_data.length == raw_data.raw_text.len());
 
-        let mut relational = HashMap::new();
+        assert!(raw_data.length == raw_data.scraped_at.len());
 
         for (i, entry) in raw_data.raw_text.iter().enumerate() {
-            relational.insert(entry.unique_string(), i);
+            raw_data.relational.insert(entry.unique_string(), i);
+            raw_data.lexical.add_document(i, &entry.lexical_fields());
         }
 
-        Database {
+        raw_data.quantized = QuantizedVectors::from_env(EMBEDDING_DIMS);
+        for i in 0..raw_data.length {
+            match &raw_data.processed[i] {
+                Some(computed) => raw_data.quantized.push(&computed.embedding),
+                None => raw_data.quantized.push(&[0.0; EMBEDDING_DIMS]),
+            }
+        }
+
+        match Self::load_hnsw(name) {
+            Some(index) if index.len() == raw_data.length => raw_data.vectors = index,
+            _ => {
+                eprintln!("rebuilding hnsw index for {} entries", raw_data.length);
+                for i in 0..raw_data.length {
+                    raw_data.index_graph(i);
+                }
+            }
+        }
+
+        // anything added after the last snapshot
+        let (wal, records) =
+            WriteAheadLog::open(name).unwrap_or_else(|e| panic!("cant open wal for {name}: {e}"));
+        let mut replayed = 0;
+        for record in records {
+            match record {
+                WalRecord::Add {
+                    page,
+                    computed,
+                    scraped_at,
+                } => {
+                    raw_data.upsert_entry(page, computed, scraped_at);
+                    replayed += 1;
+                }
+                WalRecord::SetExtras { key, computed } => {
+                    match raw_data.relational.get(&key) {
+                        Some(&index) => raw_data.set_computed(index, Some(computed)),
+                        None => eprintln!("wal has extras for missing entry {}", key.0),
+                    }
+                    replayed += 1;
+                }
+            }
+        }
+        if replayed > 0 {
+            eprintln!("replayed {replayed} entries from the wal");
+        }
+
+        let database = Database {
             raw_data: RwLock::new(raw_data),
-            relational,
             file_location: name,
-            ollama: OllamaEmbedder::new(),
+            embedder: embedder::from_env(EMBEDDING_DIMS),
+            query_cache: QueryCache::from_env(),
+            wal: Mutex::new(wal),
+            media: MediaArchive::from_env(),
+        };
+        // written in the new format right away so the json is only ever read once
+        if from_legacy && let Err(e) = database.save() {
+            eprintln!("cant save converted database to {name}: {e}");
         }
+        database
     }
-    pub fn save_json(&self) {
+    // the archive from before the binary format sits next to it as .json, decode
+    // tells the two apart by their first bytes
+    fn load_legacy(name: &str) -> Option<UnderlyingData> {
+        let legacy = Path::new(name).with_extension("json");
+        if legacy == Path::new(name) {
+            return None;
+        }
+        let bytes = fs::read(&legacy).ok().filter(|bytes| !bytes.is_empty())?;
+        match storage::decode(&bytes) {
+            Ok(good) => {
+                eprintln!("loaded {}, saving to {name} from now on", legacy.display());
+                Some(good)
+            }
+            Err(e) => panic!("cant load database {}: {e}", legacy.display()),
+        }
+    }
+    pub fn with_embedder(mut self, embedder: Box<dyn Embedder>) -> Database {
+        self.embedder = embedder;
+        self.query_cache.clear();
+        self
+    }
+    // json or binary depending on the file name, see StorageFormat::from_path
+    pub fn save(&self) -> io::Result<()> {
         let _guard = self.raw_data.write().unwrap();
         let data = &*_guard;
-        let json_string = serde_json::to_string_pretty(data).unwrap();
+        let bytes = storage::encode(data, StorageFormat::from_path(self.file_location))?;
+        storage::write_atomic(self.file_location, &bytes)?;
+
+        let hnsw_bytes = serde_json::to_vec(&data.vectors).map_err(io::Error::other)?;
+        storage::write_atomic(&Self::hnsw_location(self.file_location), &hnsw_bytes)?;
 
-        let mut file = fs::File::create(self.file_location).unwrap();
-        file.write_all(json_string.as_bytes()).unwrap();
+        // everything in the wal is part of the snapshot now
+        self.wal.lock().unwrap().truncate()
     }
+    fn hnsw_location(name: &str) -> String {
+        format!("{name}.hnsw")
+    }
+    fn load_hnsw(name: &str) -> Option<HnswIndex> {
+        let data = fs::read_to_string(Self::hnsw_location(name)).ok()?;
+        serde_json::from_str(&data).ok()
+    }
+
+    // insert or replace by unique string, only goes to the embedder when the embedded text changed
+    pub async fn add_entry(&self, entry: ScrapedMainPageEnum) -> AddOutcome {
+        self.add_entries(vec![entry]).await.remove(0)
+    }
+    // add_entry for many at once, one trip to the embedder and one short write lock.
+    // results line up with entries
+    pub async fn add_entries(&self, entries: Vec<ScrapedMainPageEnum>) -> Vec<AddOutcome> {
+        let existing: Vec<Option<(ScrapedMainPageEnum, Option<ComputedData>)>> = {
+            let data = self.raw_data.read().unwrap();
+            entries
+                .iter()
+                .map(|entry| {
+                    data.relational
+                        .get(&entry.unique_string())
+                        .map(|&index| (data.raw_text[index].clone(), data.processed[index].clone()))
+                })
+                .collect()
+        };
+
+        let needs_embedding: Vec<usize> = (0..entries.len())
+            .filter(|&i| match &existing[i] {
+                Some((old_page, _)) => {
+                    *old_page != entries[i]
+                        && old_page.embedding_text() != entries[i].embedding_text()
+                }
+                None => true,
+            })
+            .collect();
+        let texts: Vec<String> = needs_embedding
+            .iter()
+            .map(|&i| entries[i].embedding_text())
+            .collect();
+        let mut embeds: Vec<Option<Result<[f32; EMBEDDING_DIMS], EmbedError>>> =
+            entries.iter().map(|_| None).collect();
+        for (i, embed) in needs_embedding
+            .into_iter()
+            .zip(self.embedder.embed_batch(&texts).await)
+        {
+            embeds[i] = Some(embed.and_then(Self::stored_size));
+        }
+
+        let scraped_at = history::now();
+        let mut data = self.raw_data.write().unwrap();
+        let mut wal = self.wal.lock().unwrap();
+        let mut records = vec![];
+        let outcomes = entries
+            .into_iter()
+            .zip(embeds)
+            .map(|(entry, embed)| {
+                // looked up again, someone else (or an earlier entry in this batch) could
+                // have changed it while we were embedding
+                let current = data.relational.get(&entry.unique_string()).copied();
+                if let Some(index) = current
+                    && data.raw_text[index] == entry
+                {
+                    return AddOutcome::Unchanged { id: index };
+                }
+                let old = current.and_then(|index| data.processed[index].clone());
 
-    pub async fn add_entry(&self, entry: ScrapedMainPageEnum) {
-        if let Some(existing_idx) = self.relational.get(&entry.unique_string()) {
-            // let mut data = self.raw_data.write().unwrap();
-            // data.raw_text[*existing_idx] = entry;
-            // data.processed[*existing_idx] = None;
+                let computed = match embed {
+                    Some(Ok(embedding)) => {
+                        let model = self.embedder.model();
+                        Some(match &old {
+                            // scores from offline jobs belong to the project, not the text
+                            Some(old) => ComputedData {
+                                embedding,
+                                model: model.to_owned(),
+                                outdated: false,
+                                ..old.clone()
+                            },
+                            None => ComputedData::new(embedding, model),
+                        })
+                    }
+                    // stored anyway, a new entry without a vector and an update with its
+                    // old one, the retry worker catches both up later
+                    Some(Err(e)) => {
+                        eprintln!(
+                            "storing {} without a new embedding: {e}",
+                            entry.unique_string().0
+                        );
+                        old.clone().map(|old| ComputedData {
+                            outdated: true,
+                            ..old
+                        })
+                    }
+                    // text unchanged when we looked, so whatever is stored now is still
+                    // right. not the copy from before the await, set_extras or the retry
+                    // worker could have written since
+                    None => match current {
+                        Some(index)
+                            if data.raw_text[index].embedding_text() != entry.embedding_text() =>
+                        {
+                            // the text was changed in between, the stored vector is of theirs not ours
+                            old.clone().map(|old| ComputedData {
+                                outdated: true,
+                                ..old
+                            })
+                        }
+                        _ => old.clone(),
+                    },
+                };
+                let reembedded =
+                    old.as_ref().map(|c| c.embedding) != computed.as_ref().map(|c| c.embedding);
+                let pending = computed.as_ref().is_none_or(|c| c.outdated);
+
+                records.push(WalRecord::Add {
+                    page: entry.clone(),
+                    computed: computed.clone(),
+                    scraped_at,
+                });
+                let id = data.upsert_entry(entry, computed, scraped_at);
+                match current {
+                    Some(_) => AddOutcome::Updated {
+                        id,
+                        reembedded,
+                        pending,
+                    },
+                    None => AddOutcome::Inserted { id, pending },
+                }
+            })
+            .collect();
+        let written = wal.append(&records);
+        drop(wal);
+        drop(data);
+        Self::sync_wal(written);
+        outcomes
+    }
+    // one fsync per batch and outside the locks, searches dont wait on the disk
+    fn sync_wal(written: io::Result<PendingSync>) {
+        if let Err(e) = written.and_then(PendingSync::wait) {
+            eprintln!("cant append to wal: {e}");
+        }
+    }
+    // the stored copy is a fixed size array, so a model with the wrong width is an error not a panic
+    fn stored_size(embed: Vec<f32>) -> Result<[f32; EMBEDDING_DIMS], EmbedError> {
+        let got = embed.len();
+        embed.try_into().map_err(|_| EmbedError::Dimensions {
+            expected: EMBEDDING_DIMS,
+            got,
+        })
+    }
+    // query is in the query.rs syntax, only malformed queries fail.
+    // a cursor in paging is trusted to belong to this query, check its fingerprint first
+    pub async fn search_and_rank_json(
+        &self,
+        query: String,
+        paging: &Paging,
+        options: &SearchOptions,
+    ) -> Result<String, QueryError> {
+        let parsed = query::parse(&query)?;
+        let text = parsed.ranking_text();
+        let fingerprint = paging::fingerprint(&query, options);
+
+        // skip the embedder round trip when the caller only wants keywords
+        let embed = if options.wants_semantic() {
+            match self
+                .query_cache
+                .embed(&*self.embedder, &text)
+                .await
+                .and_then(Self::stored_size)
+            {
+                Ok(embed) => Some(embed),
+                Err(e) => {
+                    eprintln!("embedder unavailable, keyword only search: {e}");
+                    None
+                }
+            }
         } else {
-            let embed = self
-                .ollama
-                .generate((&entry.preview().description).into()).await
-                .unwrap()[0]
-                .clone()
-                .try_into()
-                .unwrap();
-            let mut data = self.raw_data.write().unwrap();
-            data.raw_text.push(entry);
-            data.processed.push(Some(ComputedData {
-                embedding: embed,
-                ai_description: 0.0,
-                ai_code: 0.0,
-            })); //
-            data.length += 1;
-        }
-    }
-    pub async fn search_and_rank_json(&self, query: String, k: usize) -> String {
-        let embed = &self.ollama.generate(&query).await.unwrap()[0];
+            None
+        };
+        let degraded = options.wants_semantic() && embed.is_none();
+        // without a query vector its a keyword search, whatever weight was asked for
+        let options = &SearchOptions {
+            semantic_weight: if degraded {
+                0.0
+            } else {
+                options.semantic_weight
+            },
+            ..*options
+        };
         let data = self.raw_data.read().unwrap();
-        let mut min_heap: BinaryHeap<Reverse<(OrderedFloat<f32>, usize)>> =
-            BinaryHeap::with_capacity(50);
+        let snapshot = paging.snapshot(data.length);
+        let mut allowed = parsed
+            .has_constraints()
+            .then(|| parsed.allowed(&data.lexical, &data.raw_text));
+        if snapshot < data.length {
+            let allowed = allowed.get_or_insert_with(|| vec![true; data.length]);
+            allowed[snapshot..].fill(false);
+        }
+        let is_allowed = |i: usize| allowed.as_ref().is_none_or(|allowed| allowed[i]);
 
-        for i in 0..data.length {
-            let page = &data.raw_text[i];
-            let extra = &data.processed[i];
+        let multiplier = |i: usize| {
+            data.processed[i]
+                .as_ref()
+                .map_or(1.0, |computed| computed.score_multiplier)
+        };
+        // the top k fused, and how many allowed docs matched a keyword
+        let rank = |k: usize| {
+            let candidates = options.candidate_count(k);
+            let semantic = match &embed {
+                Some(embed) => Self::with_unembedded(
+                    &data,
+                    Self::semantic_search(
+                        &data,
+                        embed,
+                        self.embedder.model(),
+                        candidates,
+                        options,
+                        text.is_empty(),
+                        allowed.as_deref(),
+                    ),
+                    self.embedder.model(),
+                    &text,
+                    candidates,
+                    allowed.as_deref(),
+                ),
+                None => vec![],
+            };
+            let (lexical, lexical_hits) = if text.is_empty() && embed.is_none() {
+                // bm25 scores nothing without words. blank and filter only queries list
+                // whatever is allowed by each event's own rank, the order from before
+                // embeddings, so the homepage still fills with the embedder down
+                let listed: Vec<usize> = (0..data.length).filter(|&i| is_allowed(i)).collect();
+                let ranked = Self::semantic_top_k(&listed, candidates, |i| {
+                    data.raw_text[i].rank(&text, &data.processed[i])
+                });
+                (ranked, listed.len())
+            } else if options.wants_lexical() {
+                data.lexical.search_where(&text, candidates, is_allowed)
+            } else {
+                (vec![], 0)
+            };
+            let fused = ranking::fuse(&semantic, &lexical, k, options, multiplier);
+            (fused, lexical_hits)
+        };
 
-            let current_rank = OrderedFloat(OllamaEmbedder::comparare_cos(
-                &embed,
-                &data.processed[i].as_ref().unwrap().embedding,
-            ));
-            // let current_rank = OrderedFloat(page.rank(&query, extra));
-            if current_rank.0 < 0.0 && !query.is_empty() {
-                continue;
+        // only as deep as the page asked for. a cursor page can still come up short while
+        // there is more, deeper candidate lists can lift docs that were just past them
+        // above the cursor and push out the ones after it, so those look again deeper
+        let mut k = paging.fetch_count();
+        let (page, next, lexical_hits) = loop {
+            let (fused, lexical_hits) = rank(k);
+            let exhausted = fused.len() < k;
+            let (page, next) = paging.slice(fused, snapshot, fingerprint);
+            if next.is_some()
+                || exhausted
+                || paging.cursor.is_none()
+                || paging.depth() + page.len() >= paging::MAX_DEPTH
+                || k > paging::MAX_DEPTH
+            {
+                break (page, next, lexical_hits);
             }
+            k *= 2;
+        };
+
+        // anything allowed has some similarity, so with embeddings on its everything left
+        let total_estimate = if options.wants_semantic() {
+            allowed.as_ref().map_or(data.length, |allowed| {
+                allowed.iter().filter(|allowed| **allowed).count()
+            })
+        } else {
+            lexical_hits
+        };
+
+        Ok(self.results_json(
+            &data,
+            page,
+            total_estimate,
+            paging.depth(),
+            next.map(|cursor| cursor.encode()),
+            degraded,
+        ))
+    }
+    fn semantic_search(
+        data: &UnderlyingData,
+        embed: &[f32],
+        model: &str,
+        k: usize,
+        options: &SearchOptions,
+        keep_negative: bool,
+        allowed: Option<&[bool]>,
+    ) -> Vec<(f32, usize)> {
+        let exact = |i: usize| embedder::comparare_cos(embed, data.embedding(i));
+        // entries still waiting on the embedder arent linked into the graph either, and
+        // vectors from another model are in a different space than the query
+        let searchable = |i: usize| {
+            data.processed[i]
+                .as_ref()
+                .is_some_and(|computed| computed.model == model)
+                && allowed.is_none_or(|allowed| allowed[i])
+        };
+        let candidates: Vec<usize> = (0..data.length).filter(|&i| searchable(i)).collect();
+        let use_graph = candidates.len() >= BRUTE_FORCE_LIMIT;
+        // the graph cant skip nodes while walking, so overfetch by how much the filter
+        // throws away and drop the rest afterwards
+        let graph_k = |k: usize| {
+            if candidates.len() < data.length {
+                (k * data.length / candidates.len().max(1)).min(data.length)
+            } else {
+                k
+            }
+        };
+        let graph_search = |k: usize, similarity: &dyn Fn(usize) -> f32| {
+            let mut found =
+                data.vectors
+                    .search_by(graph_k(k), options.ef_search.max(graph_k(k)), similarity);
+            found.retain(|(_, i)| searchable(*i));
+            found.truncate(k);
+            found
+        };
+
+        // asking for a copy that isnt kept gets full precision
+        let rough_pass = options.quantization != Quantization::None && data.quantized.keeps();
+        let mut found = if !rough_pass {
+            if use_graph {
+                graph_search(k, &exact)
+            } else {
+                Self::semantic_top_k(&candidates, k, exact)
+            }
+        } else {
+            // rough pass over the small copies, then only the survivors get full precision
+            let query = quantize::quantize_query(embed);
+            let rough = |i: usize| {
+                data.quantized
+                    .similarity(options.quantization, &query, i)
+                    .unwrap_or_else(|| exact(i))
+            };
+            let first_pass = k * options.rescore_factor.max(1);
+            let rough_top = if use_graph {
+                graph_search(first_pass, &rough)
+            } else {
+                Self::semantic_top_k(&candidates, first_pass, rough)
+            };
+
+            let mut rescored: Vec<(f32, usize)> =
+                rough_top.into_iter().map(|(_, i)| (exact(i), i)).collect();
+            rescored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+            rescored.truncate(k);
+            rescored
+        };
+
+        if !keep_negative {
+            found.retain(|(rank, _)| *rank >= 0.0);
+        }
+        found
+    }
+    // entries still waiting on the embedder (or on a re-embed with the current model) have
+    // no similarity to sort by, so only the ones matching the query keywords come along.
+    // their bm25 relative to the best bm25 of any doc scales the top cosine score, neither
+    // depends on how deep the page is so they keep their spot from page to page
+    fn with_unembedded(
+        data: &UnderlyingData,
+        mut semantic: Vec<(f32, usize)>,
+        model: &str,
+        text: &str,
+        k: usize,
+        allowed: Option<&[bool]>,
+    ) -> Vec<(f32, usize)> {
+        let pending = |i: usize| {
+            data.processed[i]
+                .as_ref()
+                .is_none_or(|computed| computed.model != model)
+                && allowed.is_none_or(|allowed| allowed[i])
+        };
+        if !(0..data.length).any(pending) {
+            return semantic;
+        }
+
+        let top = semantic.first().map_or(1.0, |(score, _)| *score);
+        let fallback: Vec<(f32, usize)> = if text.is_empty() {
+            // only filters, every pending entry matches and none is more relevant. they go
+            // after the embedded ones in their event's rank order, squashed into [-2, -1)
+            // where no cosine reaches
+            let listed: Vec<usize> = (0..data.length).filter(|&i| pending(i)).collect();
+            Self::semantic_top_k(&listed, k, |i| {
+                let rank = data.raw_text[i].rank(text, &data.processed[i]).max(0.0);
+                -2.0 + rank / (1.0 + rank)
+            })
+        } else {
+            let scores = data.lexical.score_all(text);
+            let best = scores.iter().copied().fold(0.0, f32::max);
+            let matching: Vec<usize> = (0..data.length)
+                .filter(|&i| scores[i] > 0.0 && pending(i))
+                .collect();
+            Self::semantic_top_k(&matching, k, |i| scores[i])
+                .into_iter()
+                .map(|(score, i)| (top * score / best, i))
+                .collect()
+        };
+
+        semantic.extend(fallback);
+        semantic.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        semantic.truncate(k);
+        semantic
+    }
+    fn semantic_top_k(
+        candidates: &[usize],
+        k: usize,
+        similarity: impl Fn(usize) -> f32,
+    ) -> Vec<(f32, usize)> {
+        let mut min_heap: BinaryHeap<Reverse<(OrderedFloat<f32>, usize)>> =
+            BinaryHeap::with_capacity(50);
+
+        for &i in candidates {
+            let current_rank = OrderedFloat(similarity(i));
             let heap_item = Reverse((current_rank, i));
 
             if min_heap.len() < k {
@@ -138,25 +814,553 @@ impl Database {
                 min_heap.push(heap_item);
             }
         }
-        let mut top_page_info: Vec<(OrderedFloat<f32>, usize)> = min_heap
+        let mut top_page_info: Vec<(f32, usize)> = min_heap
+            .into_iter()
+            .map(|Reverse((rank, original_index))| (rank.0, original_index))
+            .collect();
+
+        top_page_info.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        top_page_info
+    }
+    // entries with a vector from some other model than the current embedder
+    pub fn stale_embeddings(&self) -> Vec<usize> {
+        let model = self.embedder.model();
+        let data = self.raw_data.read().unwrap();
+        (0..data.length)
+            .filter(|&i| {
+                data.processed[i]
+                    .as_ref()
+                    .is_some_and(|computed| computed.model != model)
+            })
+            .collect()
+    }
+    pub fn embedding_models(&self) -> BTreeMap<String, usize> {
+        let data = self.raw_data.read().unwrap();
+        let mut models = BTreeMap::new();
+        for computed in data.processed.iter().flatten() {
+            *models.entry(computed.model.clone()).or_default() += 1;
+        }
+        models
+    }
+    // stored while the embedder was down, no vector or one for an older text
+    pub fn pending_embeddings(&self) -> Vec<usize> {
+        let data = self.raw_data.read().unwrap();
+        (0..data.length)
+            .filter(|&i| data.processed[i].as_ref().is_none_or(|c| c.outdated))
+            .collect()
+    }
+    // for /admin/pending, oldest scrape first since those have waited longest
+    pub fn pending_report(&self) -> Vec<PendingEntry> {
+        let pending = self.pending_embeddings();
+        let data = self.raw_data.read().unwrap();
+        let mut report: Vec<PendingEntry> = pending
             .into_iter()
-            .map(|reverse_item| reverse_item.0)
+            .map(|i| {
+                let page = &data.raw_text[i];
+                let key = page.unique_string();
+                PendingEntry {
+                    id: i,
+                    name: page.preview().name,
+                    outdated: data.processed[i].is_some(),
+                    scraped_at: data.scraped_at[i],
+                    key,
+                }
+            })
             .collect();
+        report.sort_by_key(|entry| (entry.scraped_at, entry.id));
+        report
+    }
+    // new vectors from the current embedder for these entries, see reembed.rs.
+    // results line up with indexes
+    pub async fn reembed(&self, indexes: &[usize]) -> Vec<Reembedded> {
+        let model = self.embedder.model().to_owned();
+        let inputs: Vec<(UniqueString, String)> = {
+            let data = self.raw_data.read().unwrap();
+            indexes
+                .iter()
+                .map(|&i| {
+                    let page = &data.raw_text[i];
+                    (page.unique_string(), page.embedding_text())
+                })
+                .collect()
+        };
+        let texts: Vec<String> = inputs.iter().map(|(_, text)| text.clone()).collect();
+        let embeds = self.embedder.embed_batch(&texts).await;
 
-        top_page_info.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        let mut data = self.raw_data.write().unwrap();
+        let mut wal = self.wal.lock().unwrap();
+        let mut records = vec![];
+        let results = indexes
+            .iter()
+            .zip(inputs)
+            .zip(embeds)
+            .map(|((&index, (key, text)), embed)| {
+                let embedding = match embed.and_then(Self::stored_size) {
+                    Ok(embedding) => embedding,
+                    Err(e) => return Reembedded::Failed(e),
+                };
+                let page = &data.raw_text[index];
+                if page.unique_string() != key || page.embedding_text() != text {
+                    return Reembedded::Skipped;
+                }
+                let computed = match data.processed[index].clone() {
+                    Some(computed) if computed.model == model && !computed.outdated => {
+                        return Reembedded::Skipped;
+                    }
+                    Some(computed) => ComputedData {
+                        embedding,
+                        model: model.clone(),
+                        outdated: false,
+                        ..computed
+                    },
+                    None => ComputedData::new(embedding, &model),
+                };
 
-        let top_pages = top_page_info
+                records.push(WalRecord::SetExtras {
+                    key,
+                    computed: computed.clone(),
+                });
+                data.set_computed(index, Some(computed));
+                Reembedded::Done
+            })
+            .collect();
+        let written = wal.append(&records);
+        drop(wal);
+        drop(data);
+        Self::sync_wal(written);
+        results
+    }
+    pub fn key_for(&self, index: usize) -> Option<UniqueString> {
+        let data = self.raw_data.read().unwrap();
+        data.raw_text.get(index).map(|page| page.unique_string())
+    }
+    pub fn index_for(&self, key: &UniqueString) -> Option<usize> {
+        let data = self.raw_data.read().unwrap();
+        data.relational.get(key).copied()
+    }
+    pub fn details(&self, index: usize) -> Option<ProjectDetails> {
+        let data = self.raw_data.read().unwrap();
+        let page = data.raw_text.get(index)?;
+        let key = page.unique_string();
+        let mut page = page.clone();
+        self.media.rewrite(&mut page);
+        Some(ProjectDetails {
+            id: index,
+            revisions: data.history.get(&key).map_or(0, Vec::len) + 1,
+            key,
+            page,
+            scores: data.processed[index].as_ref().map(ComputedData::scores),
+        })
+    }
+    // projects linking images that look like these dhashes, see MediaArchive::similar
+    pub fn similar_images(
+        &self,
+        hashes: &[u64],
+        max_distance: u32,
+        exclude: Option<&UniqueString>,
+    ) -> Vec<SimilarProject> {
+        let matches = self.media.similar(hashes, max_distance, exclude);
+        let data = self.raw_data.read().unwrap();
+        matches
             .into_iter()
-            .map(|(rank, original_index)| DetailedSearchResult {
-                rank: rank.0,
-                id: original_index,
-                event: data.raw_text[original_index].unique_string().0,
-                page: data.raw_text[original_index].preview(),
+            .filter_map(|found| {
+                let &id = data.relational.get(&found.key)?;
+                Some(SimilarProject {
+                    id,
+                    name: data.raw_text[id].preview().name,
+                    key: found.key,
+                    distance: found.distance,
+                    image: format!("/media/{}", found.blob),
+                    source: found.source,
+                })
+            })
+            .collect()
+    }
+    pub fn describe_duplicates(&self, pairs: Vec<DuplicatePair>) -> Vec<DuplicateView> {
+        let data = self.raw_data.read().unwrap();
+        let project = |key: UniqueString| {
+            let &id = data.relational.get(&key)?;
+            let page = &data.raw_text[id];
+            let fields = page.filter_fields();
+            Some(ProjectRef {
+                id,
+                name: page.preview().name,
+                event: fields.event,
+                author: fields.author.to_owned(),
+                key,
+            })
+        };
+        pairs
+            .into_iter()
+            .filter_map(|pair| {
+                Some(DuplicateView {
+                    a: project(pair.a)?,
+                    b: project(pair.b)?,
+                    cosine: pair.cosine,
+                    jaccard: pair.jaccard,
+                    same_event: pair.same_event,
+                    same_author: pair.same_author,
+                    score: pair.score,
+                })
+            })
+            .collect()
+    }
+    pub fn history(&self, key: &UniqueString) -> Option<Vec<Revision>> {
+        let data = self.raw_data.read().unwrap();
+        data.revisions(key)
+    }
+    // from and to are positions in the history chain, 0 is the first scrape
+    pub fn revision_diff(
+        &self,
+        key: &UniqueString,
+        from: usize,
+        to: usize,
+    ) -> Option<RevisionDiff> {
+        let data = self.raw_data.read().unwrap();
+        history::diff(&data.revisions(key)?, from, to)
+    }
+    fn results_json(
+        &self,
+        data: &UnderlyingData,
+        ranked: Vec<(f32, usize)>,
+        total_estimate: usize,
+        offset: usize,
+        next_cursor: Option<String>,
+        degraded: bool,
+    ) -> String {
+        let results = ranked
+            .into_iter()
+            .map(|(rank, original_index)| {
+                let mut page = data.raw_text[original_index].preview();
+                self.media.rewrite_preview(&mut page);
+                DetailedSearchResult {
+                    rank,
+                    id: original_index,
+                    event: data.raw_text[original_index].unique_string().0,
+                    page,
+                }
             })
             .collect::<Vec<DetailedSearchResult>>();
 
-        serde_json::to_string(&top_pages).unwrap()
+        serde_json::to_string(&SearchResultsPage {
+            results,
+            total_estimate,
+            offset,
+            next_cursor,
+            degraded,
+        })
+        .unwrap()
+    }
+
+    pub fn set_extras(&self, index: usize, extras: Extras) -> Result<ComputedData, ExtrasError> {
+        let finite = |value: Option<f32>, field| match value {
+            Some(value) if !value.is_finite() => Err(ExtrasError::BadValue(field)),
+            _ => Ok(value),
+        };
+        let ai_description = finite(extras.ai_description, "ai_description")?;
+        let ai_code = finite(extras.ai_code, "ai_code")?;
+        let score_multiplier = match finite(extras.score_multiplier, "score_multiplier")? {
+            // a negative multiplier would flip the order instead of burying it
+            Some(value) if value < 0.0 => return Err(ExtrasError::BadValue("score_multiplier")),
+            value => value,
+        };
+        let embedding: Option<[f32; EMBEDDING_DIMS]> = match extras.embedding {
+            Some(embedding) => {
+                let got = embedding.len();
+                if embedding.iter().any(|x| !x.is_finite()) {
+                    return Err(ExtrasError::BadValue("embedding"));
+                }
+                Some(
+                    embedding
+                        .try_into()
+                        .map_err(|_| ExtrasError::WrongDimensions { got })?,
+                )
+            }
+            None => None,
+        };
+
+        let mut data = self.raw_data.write().unwrap();
+        if index >= data.length {
+            return Err(ExtrasError::NotFound);
+        }
+        let model = extras
+            .model
+            .unwrap_or_else(|| self.embedder.model().to_owned());
+        // a pending entry can be given its vector here instead of waiting on the embedder
+        let mut computed = match (data.processed[index].clone(), embedding) {
+            (Some(computed), _) => computed,
+            (None, Some(embedding)) => ComputedData::new(embedding, &model),
+            (None, None) => return Err(ExtrasError::NotEmbedded),
+        };
+        if let Some(value) = ai_description {
+            computed.ai_description = value;
+        }
+        if let Some(value) = ai_code {
+            computed.ai_code = value;
+        }
+        if let Some(value) = score_multiplier {
+            computed.score_multiplier = value;
+        }
+        if let Some(embedding) = embedding {
+            computed.embedding = embedding;
+            computed.model = model;
+            // whatever text it was behind on, this vector replaces it
+            computed.outdated = false;
+        }
+
+        let record = WalRecord::SetExtras {
+            key: data.raw_text[index].unique_string(),
+            computed: computed.clone(),
+        };
+        let written = self.wal.lock().unwrap().append(&[record]);
+        data.set_computed(index, Some(computed.clone()));
+        drop(data);
+        Self::sync_wal(written);
+        Ok(computed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::Summer2025MainPage,
+        embedder::{EmbedFuture, HashEmbedder},
+        paging::Cursor,
+        ranking::Fusion,
+    };
+    use std::sync::Arc;
+    use tokio::sync::Notify;
+
+    // fails on "down", holds "slow" until released, everything else is the hash embedder
+    struct Gated {
+        hash: HashEmbedder,
+        started: Arc<Notify>,
+        release: Arc<Notify>,
+    }
+
+    impl Embedder for Gated {
+        fn model(&self) -> &str {
+            self.hash.model()
+        }
+        fn embed<'a>(&'a self, text: &'a str) -> EmbedFuture<'a> {
+            Box::pin(async move {
+                if text.contains("down") {
+                    return Err(EmbedError::Request("down".into()));
+                }
+                if text.contains("slow") {
+                    self.started.notify_one();
+                    self.release.notified().await;
+                }
+                Ok(self.hash.embed_now(text))
+            })
+        }
+    }
+
+    fn page(url: &str, description: &str, followers: u16) -> ScrapedMainPageEnum {
+        ScrapedMainPageEnum::Summer2025(Summer2025MainPage {
+            url: url.into(),
+            name: url.into(),
+            description: description.into(),
+            followers,
+            ..Default::default()
+        })
     }
 
-    pub fn set_extras(&self, index: usize, computed: ComputedData) {}
+    #[tokio::test]
+    async fn add_keeps_extras_set_while_embedding() {
+        let started = Arc::new(Notify::new());
+        let release = Arc::new(Notify::new());
+        let db = Database::new_non_backed().with_embedder(Box::new(Gated {
+            hash: HashEmbedder::new(EMBEDDING_DIMS),
+            started: started.clone(),
+            release: release.clone(),
+        }));
+        db.add_entry(page("x", "down for now", 0)).await;
+        assert_eq!(db.pending_embeddings(), vec![0]);
+
+        // x keeps its text so it isnt re-embedded, y holds the batch open while x gets
+        // its vector through set_extras
+        let (outcomes, _) = tokio::join!(
+            db.add_entries(vec![page("x", "down for now", 5), page("y", "slow", 0)]),
+            async {
+                started.notified().await;
+                let extras = Extras {
+                    embedding: Some(vec![0.5; EMBEDDING_DIMS]),
+                    ..Default::default()
+                };
+                db.set_extras(0, extras).unwrap();
+                release.notify_one();
+            }
+        );
+        assert_eq!(outcomes.len(), 2);
+
+        let data = db.raw_data.read().unwrap();
+        assert_eq!(data.raw_text[0], page("x", "down for now", 5));
+        let computed = data.processed[0].as_ref().expect("extras were reverted");
+        assert_eq!(computed.embedding, [0.5; EMBEDDING_DIMS]);
+        assert!(data.processed[1].is_some());
+    }
+
+    // every page through next_cursor, returns the ids in order
+    async fn walk_pages(db: &Database, query: &str, options: &SearchOptions) -> Vec<u64> {
+        let mut ids = vec![];
+        let mut paging = Paging::first(20);
+        loop {
+            let json = db
+                .search_and_rank_json(query.into(), &paging, options)
+                .await
+                .unwrap();
+            let page: serde_json::Value = serde_json::from_str(&json).unwrap();
+            for result in page["results"].as_array().unwrap() {
+                ids.push(result["id"].as_u64().unwrap());
+            }
+            match page["next_cursor"].as_str() {
+                Some(cursor) => paging.cursor = Some(Cursor::decode(cursor).unwrap()),
+                None => return ids,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_cover_every_result_once() {
+        let db = Database::new_non_backed().with_embedder(Box::new(HashEmbedder::new(768)));
+        let mut rng = fastrand::Rng::with_seed(7);
+        let entries = (0..300)
+            .map(|i| {
+                let description: Vec<String> =
+                    (0..12).map(|_| format!("w{}", rng.u32(0..60))).collect();
+                page(&format!("p{i}"), &description.join(" "), (i % 13) as u16)
+            })
+            .collect();
+        db.add_entries(entries).await;
+
+        for fusion in [Fusion::Rrf, Fusion::Weighted] {
+            let options = SearchOptions {
+                fusion,
+                ..Default::default()
+            };
+            let all = db
+                .search_and_rank_json("w1 w2".into(), &Paging::first(300), &options)
+                .await
+                .unwrap();
+            let all: serde_json::Value = serde_json::from_str(&all).unwrap();
+            let all: Vec<u64> = all["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|result| result["id"].as_u64().unwrap())
+                .collect();
+            assert_eq!(all.len(), 300);
+
+            let mut paged = walk_pages(&db, "w1 w2", &options).await;
+            assert_eq!(paged.len(), 300, "{fusion:?}");
+            paged.sort();
+            paged.dedup();
+            assert_eq!(paged.len(), 300, "{fusion:?} repeated a result");
+
+            let mut offsets = vec![];
+            for offset in (0..300).step_by(20) {
+                let paging = Paging {
+                    offset,
+                    ..Paging::first(20)
+                };
+                let json = db
+                    .search_and_rank_json("w1 w2".into(), &paging, &options)
+                    .await
+                    .unwrap();
+                let page: serde_json::Value = serde_json::from_str(&json).unwrap();
+                for result in page["results"].as_array().unwrap() {
+                    offsets.push(result["id"].as_u64().unwrap());
+                }
+            }
+            assert_eq!(offsets, all, "{fusion:?}");
+        }
+    }
+
+    struct Down;
+
+    impl Embedder for Down {
+        fn model(&self) -> &str {
+            "down"
+        }
+        fn embed<'a>(&'a self, _text: &'a str) -> EmbedFuture<'a> {
+            Box::pin(async { Err(EmbedError::Request("down".into())) })
+        }
+    }
+
+    async fn result_ids(db: &Database, query: &str, options: &SearchOptions) -> Vec<u64> {
+        let json = db
+            .search_and_rank_json(query.into(), &Paging::default(), options)
+            .await
+            .unwrap();
+        let page: serde_json::Value = serde_json::from_str(&json).unwrap();
+        page["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["id"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn blank_and_filter_only_queries_list_by_rank() {
+        let entries = || {
+            vec![
+                page("a", "quiet one", 1),
+                ScrapedMainPageEnum::Summer2025(Summer2025MainPage {
+                    url: "b".into(),
+                    description: "has a repo".into(),
+                    followers: 3,
+                    repo: Some("https://github.com/b/b".into()),
+                    ..Default::default()
+                }),
+                page("c", "popular", 9),
+            ]
+        };
+        let keywords_only = SearchOptions {
+            semantic_weight: 0.0,
+            ..Default::default()
+        };
+
+        let down = Database::new_non_backed().with_embedder(Box::new(Down));
+        down.add_entries(entries()).await;
+        for options in [SearchOptions::default(), keywords_only] {
+            assert_eq!(result_ids(&down, "", &options).await, vec![2, 1, 0]);
+            assert_eq!(result_ids(&down, "has:repo", &options).await, vec![1]);
+            assert_eq!(result_ids(&down, "-has:repo", &options).await, vec![2, 0]);
+        }
+
+        let up = Database::new_non_backed().with_embedder(Box::new(HashEmbedder::new(768)));
+        up.add_entries(entries()).await;
+        assert_eq!(result_ids(&up, "", &keywords_only).await, vec![2, 1, 0]);
+        assert_eq!(result_ids(&up, "has:repo", &keywords_only).await, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn pending_entries_only_show_up_when_they_match() {
+        let db = Database::new_non_backed().with_embedder(Box::new(Gated {
+            hash: HashEmbedder::new(EMBEDDING_DIMS),
+            started: Arc::new(Notify::new()),
+            release: Arc::new(Notify::new()),
+        }));
+        db.add_entries(vec![
+            page("a", "a rust discord bot", 0),
+            page("b", "baking cake recipes", 0),
+            page("c", "down, a rust discord bot for servers", 0),
+            page("d", "down, knitting patterns", 4),
+        ])
+        .await;
+        assert_eq!(db.pending_embeddings(), vec![2, 3]);
+
+        let semantic_only = SearchOptions {
+            semantic_weight: 1.0,
+            ..Default::default()
+        };
+        let found = result_ids(&db, "rust discord bot", &semantic_only).await;
+        assert!(found.contains(&2));
+        assert!(!found.contains(&3));
+        // blank, after the embedded ones in rank order
+        assert_eq!(&result_ids(&db, "", &semantic_only).await[2..], &[3, 2]);
+    }
 }
@@ -1,25 +1,243 @@
 use ollama_rs::{
     Ollama,
     generation::{
-        embeddings::request::{EmbeddingsInput, GenerateEmbeddingsRequest},
+        embeddings::{
+            GenerateEmbeddingsResponse,
+            request::{EmbeddingsInput, GenerateEmbeddingsRequest},
+        },
         parameters::{KeepAlive, TimeUnit},
     },
 };
+use serde::Deserialize;
+use std::{env, fmt, future::Future, pin::Pin, time::Duration};
+use tokio::task::JoinSet;
+
+use crate::lexical;
+
+// anything that turns text into a vector, the database only ever talks to a Box<dyn Embedder>
+// so the backend can be picked at startup (or swapped for the hash one in benches)
+
+pub type EmbedFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<f32>, EmbedError>> + Send + 'a>>;
+pub type BatchFuture<'a> =
+    Pin<Box<dyn Future<Output = Vec<Result<Vec<f32>, EmbedError>>> + Send + 'a>>;
+
+// everything stored before the model was recorded came from ollama's default
+pub const LEGACY_MODEL: &str = "nomic-embed-text:v1.5";
+
+pub trait Embedder: Send + Sync {
+    // recorded next to stored vectors, two different models never share a space
+    fn model(&self) -> &str;
+    fn embed<'a>(&'a self, text: &'a str) -> EmbedFuture<'a>;
+    // one result per text in the same order, the default just goes one at a time
+    fn embed_batch<'a>(&'a self, texts: &'a [String]) -> BatchFuture<'a> {
+        Box::pin(async move {
+            let mut embeds = Vec::with_capacity(texts.len());
+            for text in texts {
+                embeds.push(self.embed(text).await);
+            }
+            embeds
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum EmbedError {
+    // couldnt reach the service or it said no
+    Request(String),
+    // the service answered with an error status, body is whatever it said about it
+    Status { status: u16, body: String },
+    // got an answer but not a usable vector
+    Response(String),
+    Dimensions { expected: usize, got: usize },
+}
+
+impl fmt::Display for EmbedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmbedError::Request(e) => write!(f, "embedding request failed: {e}"),
+            EmbedError::Status { status, body } => {
+                write!(f, "embedding service answered {status}: {body}")
+            }
+            EmbedError::Response(e) => write!(f, "bad embedding response: {e}"),
+            EmbedError::Dimensions { expected, got } => {
+                write!(f, "embedding has {got} dimensions, expected {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EmbedError {}
+
+// error_for_status without throwing the body away, thats where the reason is
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, EmbedError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(EmbedError::Status {
+        status: status.as_u16(),
+        body: body.trim().to_owned(),
+    })
+}
+
+// picks the backend from the environment, ollama on localhost if nothing is set
+//   SOM_BACKEND_EMBEDDER   ollama | openai | hash
+//   SOM_BACKEND_EMBED_URL  base url of the service
+//   SOM_BACKEND_EMBED_MODEL
+//   SOM_BACKEND_EMBED_KEY  bearer token for openai compatible apis
+//   SOM_BACKEND_EMBED_DIMENSIONS   asks an openai compatible api to shorten its vectors,
+//                                  only for models that support it
+//   SOM_BACKEND_EMBED_BATCH        texts per ollama request when embedding in bulk
+//   SOM_BACKEND_EMBED_CONCURRENCY  ollama requests in flight at once
+pub fn from_env(dims: usize) -> Box<dyn Embedder> {
+    let url = env::var("SOM_BACKEND_EMBED_URL").ok();
+    let model = env::var("SOM_BACKEND_EMBED_MODEL").ok();
+    match env::var("SOM_BACKEND_EMBEDDER").as_deref() {
+        Ok("openai") => {
+            let mut embedder = OpenAiEmbedder::new(
+                url.as_deref().unwrap_or("https://api.openai.com/v1"),
+                model.as_deref().unwrap_or("text-embedding-3-small"),
+            );
+            // most servers reject the field for models that cant shorten, a model with
+            // the wrong width fails on its first embed instead
+            if let Some(dims) = env_number("SOM_BACKEND_EMBED_DIMENSIONS") {
+                embedder = embedder.dimensions(dims);
+            }
+            if let Ok(key) = env::var("SOM_BACKEND_EMBED_KEY") {
+                embedder = embedder.api_key(&key);
+            }
+            Box::new(embedder)
+        }
+        Ok("hash") => Box::new(HashEmbedder::new(dims)),
+        other => {
+            if let Ok(name) = other
+                && name != "ollama"
+            {
+                eprintln!("unknown embedder {name}, using ollama");
+            }
+            let mut batching = BatchConfig::default();
+            if let Some(size) = env_number("SOM_BACKEND_EMBED_BATCH") {
+                batching.chunk_size = size;
+            }
+            if let Some(concurrency) = env_number("SOM_BACKEND_EMBED_CONCURRENCY") {
+                batching.concurrency = concurrency;
+            }
+            Box::new(
+                OllamaEmbedder::from_parts(url.as_deref(), model.as_deref()).batching(batching),
+            )
+        }
+    }
+}
+
+// positive number from the environment, complains about anything else
+pub fn env_number(name: &str) -> Option<usize> {
+    let value = env::var(name).ok()?;
+    match value.parse() {
+        Ok(n) if n > 0 => Some(n),
+        _ => {
+            eprintln!("ignoring {name}={value}, expected a positive number");
+            None
+        }
+    }
+}
+
+// how embed_batch splits up work for ollama. a request with many inputs is a lot
+// cheaper than many requests with one, but one giant request stalls everything
+// else hitting the model and loses all of it on a single timeout
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    // texts per request
+    pub chunk_size: usize,
+    // requests in flight at once
+    pub concurrency: usize,
+    // extra attempts for a request after the first one fails
+    pub retries: u32,
+    // wait before the first retry, doubled every time after
+    pub backoff: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            chunk_size: 32,
+            concurrency: 4,
+            retries: 3,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
 
 pub struct OllamaEmbedder {
     ollama: Ollama,
+    // embeds go straight to /api/embed, ollama_rs keeps the status of a failed one to itself
+    api: OllamaApi,
+    model: String,
+    batching: BatchConfig,
+}
+
+#[derive(Clone)]
+struct OllamaApi {
+    client: reqwest::Client,
+    // with the trailing slash, like Ollama::url_str
+    url: String,
+}
+
+impl OllamaApi {
+    fn new(ollama: &Ollama) -> OllamaApi {
+        OllamaApi {
+            client: reqwest::Client::new(),
+            url: ollama.url_str().to_owned(),
+        }
+    }
+}
+
+impl Default for OllamaEmbedder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl OllamaEmbedder {
     pub fn new() -> OllamaEmbedder {
+        let ollama = Ollama::default();
+        Self {
+            api: OllamaApi::new(&ollama),
+            ollama,
+            model: LEGACY_MODEL.to_owned(),
+            batching: BatchConfig::default(),
+        }
+    }
+    pub fn with_url(url: &str, model: &str) -> OllamaEmbedder {
+        let ollama = Ollama::try_new(url).unwrap_or_else(|e| {
+            eprintln!("bad ollama url {url}: {e}, using localhost");
+            Ollama::default()
+        });
         Self {
-            ollama: Ollama::default(),
+            api: OllamaApi::new(&ollama),
+            ollama,
+            model: model.to_owned(),
+            batching: BatchConfig::default(),
         }
     }
-    pub async fn generate(&self, text: &String) -> Option<Vec<Vec<f32>>> {
+    pub fn batching(mut self, batching: BatchConfig) -> OllamaEmbedder {
+        self.batching = batching;
+        self
+    }
+    fn from_parts(url: Option<&str>, model: Option<&str>) -> OllamaEmbedder {
+        let default = Self::new();
+        match (url, model) {
+            (None, None) => default,
+            (url, model) => Self::with_url(
+                url.unwrap_or("http://127.0.0.1:11434"),
+                model.unwrap_or(&default.model),
+            ),
+        }
+    }
+    pub async fn generate(&self, text: &str) -> Option<Vec<Vec<f32>>> {
         let request = GenerateEmbeddingsRequest::new(
-            "nomic-embed-text:v1.5".to_owned(),
-            EmbeddingsInput::Multiple((vec![text.to_string()])),
+            self.model.clone(),
+            EmbeddingsInput::Multiple(vec![text.to_string()]),
         )
         .keep_alive(KeepAlive::Until {
             time: 1,
@@ -34,54 +252,341 @@ impl OllamaEmbedder {
             None
         }
     }
-    pub fn generate_seqentially(&self, text: &String) -> Option<Vec<Vec<f32>>> {
+    pub fn generate_seqentially(&self, text: &str) -> Option<Vec<Vec<f32>>> {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(self.generate(text))
     }
-    pub fn comparare_cos(embed_1: &[f32], embed_2: &[f32]) -> f32 {
-        assert!(embed_1.len() == embed_2.len());
-        let n = embed_1.len().min(embed_2.len());
-        if n == 0 {
-            return 0.0;
+    // one result per text in the same order. texts are sent chunk_size at a time with
+    // at most concurrency requests running, a chunk that keeps failing only fails its own texts
+    pub async fn generate_batch(&self, texts: &[String]) -> Vec<Result<Vec<f32>, EmbedError>> {
+        let config = self.batching;
+        let chunks: Vec<Vec<String>> = texts
+            .chunks(config.chunk_size.max(1))
+            .map(|chunk| chunk.to_vec())
+            .collect();
+        let mut results: Vec<Option<Embeds>> = vec![None; chunks.len()];
+
+        let mut running = JoinSet::new();
+        let mut queued = chunks.into_iter().enumerate();
+        loop {
+            while running.len() < config.concurrency.max(1) {
+                let Some((index, chunk)) = queued.next() else {
+                    break;
+                };
+                let api = self.api.clone();
+                let model = self.model.clone();
+                running
+                    .spawn(async move { (index, embed_chunk(&api, &model, chunk, config).await) });
+            }
+            match running.join_next().await {
+                Some(Ok((index, embeds))) => results[index] = Some(embeds),
+                // a panicked task leaves its slot empty, filled with errors below
+                Some(Err(e)) => eprintln!("embedding task failed: {e}"),
+                None => break,
+            }
         }
 
-        let mut dot: f32 = 0.0;
-        let mut sum_sq_a: f32 = 0.0;
-        let mut sum_sq_b: f32 = 0.0;
+        results
+            .into_iter()
+            .zip(texts.chunks(config.chunk_size.max(1)))
+            .flat_map(|(embeds, chunk)| {
+                embeds.unwrap_or_else(|| {
+                    vec![Err(EmbedError::Request("embedding task panicked".into())); chunk.len()]
+                })
+            })
+            .collect()
+    }
+}
+
+// one result per text of a chunk
+type Embeds = Vec<Result<Vec<f32>, EmbedError>>;
+
+async fn request_embeddings(
+    api: &OllamaApi,
+    model: &str,
+    texts: Vec<String>,
+) -> Result<Vec<Vec<f32>>, EmbedError> {
+    let count = texts.len();
+    let request =
+        GenerateEmbeddingsRequest::new(model.to_owned(), EmbeddingsInput::Multiple(texts))
+            .keep_alive(KeepAlive::Until {
+                time: 1,
+                unit: TimeUnit::Hours,
+            });
+    let response = api
+        .client
+        .post(format!("{}api/embed", api.url))
+        .json(&request)
+        .send()
+        .await
+        .map_err(|e| EmbedError::Request(format!("ollama with {model}: {e}")))?;
+    let response: GenerateEmbeddingsResponse = check_status(response)
+        .await?
+        .json()
+        .await
+        .map_err(|e| EmbedError::Response(format!("ollama with {model}: {e}")))?;
+    if response.embeddings.len() != count {
+        return Err(EmbedError::Response(format!(
+            "ollama returned {} embeddings for {count} texts",
+            response.embeddings.len()
+        )));
+    }
+    Ok(response.embeddings)
+}
 
-        for i in 0..n {
-            let a = embed_1[i];
-            let b = embed_2[i];
-            dot += a * b;
-            sum_sq_a += a * a;
-            sum_sq_b += b * b;
+async fn request_with_retries(
+    api: &OllamaApi,
+    model: &str,
+    texts: &[String],
+    config: BatchConfig,
+) -> Result<Vec<Vec<f32>>, EmbedError> {
+    let mut delay = config.backoff;
+    let mut attempt = 0;
+    loop {
+        match request_embeddings(api, model, texts.to_vec()).await {
+            Ok(embeds) => return Ok(embeds),
+            Err(e) if attempt < config.retries => {
+                attempt += 1;
+                eprintln!("{e}, retry {attempt}/{} in {delay:?}", config.retries);
+                // jitter so parallel chunks dont all come back at the same moment
+                let jitter = Duration::from_millis(fastrand::u64(0..=delay.as_millis() as u64 / 4));
+                tokio::time::sleep(delay + jitter).await;
+                delay *= 2;
+            }
+            Err(e) => return Err(e),
         }
+    }
+}
 
-        if sum_sq_a <= 0.0 || sum_sq_b <= 0.0 {
-            0.0
-        } else {
-            dot / (sum_sq_a.sqrt() * sum_sq_b.sqrt())
+async fn embed_chunk(
+    api: &OllamaApi,
+    model: &str,
+    chunk: Vec<String>,
+    config: BatchConfig,
+) -> Embeds {
+    match request_with_retries(api, model, &chunk, config).await {
+        Ok(embeds) => embeds.into_iter().map(Ok).collect(),
+        // usually the service is down and these fail fast too, but if its one text
+        // ollama cant handle (too long for the context) the rest still get through
+        Err(e) if chunk.len() > 1 => {
+            eprintln!("{e}, embedding {} texts one at a time", chunk.len());
+            // one retry each, a down service shouldnt cost chunk_size full backoffs
+            let single = BatchConfig {
+                retries: config.retries.min(1),
+                ..config
+            };
+            let mut embeds = Vec::with_capacity(chunk.len());
+            for text in chunk {
+                embeds.push(
+                    request_with_retries(api, model, std::slice::from_ref(&text), single)
+                        .await
+                        .map(|mut embeds| embeds.swap_remove(0)),
+                );
+            }
+            embeds
         }
+        Err(e) => vec![Err(e)],
+    }
+}
+
+impl Embedder for OllamaEmbedder {
+    fn model(&self) -> &str {
+        &self.model
     }
-    pub fn down_project(vector: &[f32], new_len: usize) -> Vec<f32> {
-        let old_len = vector.len();
-        assert!(new_len > 0 && new_len <= old_len);
+    fn embed<'a>(&'a self, text: &'a str) -> EmbedFuture<'a> {
+        Box::pin(async move {
+            let mut embeddings =
+                request_embeddings(&self.api, &self.model, vec![text.to_owned()]).await?;
+            if embeddings.is_empty() {
+                return Err(EmbedError::Response("ollama returned no embeddings".into()));
+            }
+            Ok(embeddings.swap_remove(0))
+        })
+    }
+    fn embed_batch<'a>(&'a self, texts: &'a [String]) -> BatchFuture<'a> {
+        Box::pin(self.generate_batch(texts))
+    }
+}
+
+// anything speaking the /embeddings shape of the openai api (openai, vllm, llama.cpp, lm studio..)
+pub struct OpenAiEmbedder {
+    client: reqwest::Client,
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+    // only sent when set, models with a fixed size reject it
+    dimensions: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiResponse {
+    data: Vec<OpenAiEmbedding>,
+}
+#[derive(Deserialize)]
+struct OpenAiEmbedding {
+    embedding: Vec<f32>,
+}
+
+impl OpenAiEmbedder {
+    pub fn new(base_url: &str, model: &str) -> OpenAiEmbedder {
+        OpenAiEmbedder {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            model: model.to_owned(),
+            api_key: None,
+            dimensions: None,
+        }
+    }
+    pub fn api_key(mut self, key: &str) -> OpenAiEmbedder {
+        self.api_key = Some(key.to_owned());
+        self
+    }
+    pub fn dimensions(mut self, dims: usize) -> OpenAiEmbedder {
+        self.dimensions = Some(dims);
+        self
+    }
+}
+
+impl Embedder for OpenAiEmbedder {
+    fn model(&self) -> &str {
+        &self.model
+    }
+    fn embed<'a>(&'a self, text: &'a str) -> EmbedFuture<'a> {
+        Box::pin(async move {
+            let mut body = serde_json::json!({ "model": self.model, "input": [text] });
+            if let Some(dims) = self.dimensions {
+                body["dimensions"] = dims.into();
+            }
+            let mut request = self
+                .client
+                .post(format!("{}/embeddings", self.base_url))
+                .json(&body);
+            if let Some(key) = &self.api_key {
+                request = request.bearer_auth(key);
+            }
 
-        let mut result = Vec::with_capacity(new_len);
-        let chunk_size = old_len as f32 / new_len as f32;
+            let response = request
+                .send()
+                .await
+                .map_err(|e| EmbedError::Request(e.to_string()))?;
+            let mut parsed: OpenAiResponse = check_status(response)
+                .await?
+                .json()
+                .await
+                .map_err(|e| EmbedError::Response(e.to_string()))?;
+            if parsed.data.is_empty() {
+                return Err(EmbedError::Response("no embeddings in response".into()));
+            }
+            Ok(parsed.data.swap_remove(0).embedding)
+        })
+    }
+}
 
-        for i in 0..new_len {
-            let start = (i as f32 * chunk_size).floor() as usize;
-            let end = (((i + 1) as f32 * chunk_size).ceil() as usize).min(old_len);
+// feature hashing over words and their character trigrams, no model and no network.
+// only knows about spelling, not meaning, but its deterministic and instant which is
+// what benches and offline runs need
+pub struct HashEmbedder {
+    dims: usize,
+    model: String,
+}
 
-            if start < end {
-                let sum: f32 = vector[start..end].iter().sum();
-                let avg = sum / (end - start) as f32;
-                result.push(avg);
-            } else {
-                result.push(0.0);
+impl HashEmbedder {
+    pub fn new(dims: usize) -> HashEmbedder {
+        assert!(dims > 0);
+        HashEmbedder {
+            dims,
+            model: format!("feature-hash-{dims}"),
+        }
+    }
+    pub fn embed_now(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0f32; self.dims];
+        for token in lexical::tokenize(text) {
+            self.add_feature(&mut vector, token.as_bytes(), 1.0);
+            // padded so word starts and ends count as their own trigrams
+            let padded: Vec<char> = format!(" {token} ").chars().collect();
+            for gram in padded.windows(3) {
+                let gram: String = gram.iter().collect();
+                self.add_feature(&mut vector, gram.as_bytes(), 0.5);
             }
         }
-        result
+
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|x| *x /= norm);
+        }
+        vector
+    }
+    fn add_feature(&self, vector: &mut [f32], feature: &[u8], weight: f32) {
+        let hash = fnv1a(feature);
+        let slot = (hash % self.dims as u64) as usize;
+        // sign from a different bit so collisions cancel out instead of piling up
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[slot] += sign * weight;
+    }
+}
+
+// std's hasher is allowed to change between releases, this one never will
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+impl Embedder for HashEmbedder {
+    fn model(&self) -> &str {
+        &self.model
+    }
+    fn embed<'a>(&'a self, text: &'a str) -> EmbedFuture<'a> {
+        Box::pin(async move { Ok(self.embed_now(text)) })
+    }
+}
+
+pub fn comparare_cos(embed_1: &[f32], embed_2: &[f32]) -> f32 {
+    assert!(embed_1.len() == embed_2.len());
+    let n = embed_1.len().min(embed_2.len());
+    if n == 0 {
+        return 0.0;
+    }
+
+    let mut dot: f32 = 0.0;
+    let mut sum_sq_a: f32 = 0.0;
+    let mut sum_sq_b: f32 = 0.0;
+
+    for i in 0..n {
+        let a = embed_1[i];
+        let b = embed_2[i];
+        dot += a * b;
+        sum_sq_a += a * a;
+        sum_sq_b += b * b;
+    }
+
+    if sum_sq_a <= 0.0 || sum_sq_b <= 0.0 {
+        0.0
+    } else {
+        dot / (sum_sq_a.sqrt() * sum_sq_b.sqrt())
+    }
+}
+pub fn down_project(vector: &[f32], new_len: usize) -> Vec<f32> {
+    let old_len = vector.len();
+    assert!(new_len > 0 && new_len <= old_len);
+
+    let mut result = Vec::with_capacity(new_len);
+    let chunk_size = old_len as f32 / new_len as f32;
+
+    for i in 0..new_len {
+        let start = (i as f32 * chunk_size).floor() as usize;
+        let end = (((i + 1) as f32 * chunk_size).ceil() as usize).min(old_len);
+
+        if start < end {
+            let sum: f32 = vector[start..end].iter().sum();
+            let avg = sum / (end - start) as f32;
+            result.push(avg);
+        } else {
+            result.push(0.0);
+        }
     }
+    result
 }
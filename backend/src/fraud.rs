@@ -0,0 +1,317 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::{
+    data::{DatabasePage, UniqueString},
+    database::Database,
+    embedder, history, lexical, storage,
+};
+
+// near duplicate projects, the same project handed in twice, across events or by two
+// people. two signals per pair: cosine between the embeddings, which survives rewording,
+// and minhash over word shingles of the description and devlogs, which catches copy
+// paste. a scan looks at every project's nearest embeddings and at everything sharing a
+// minhash band with it, and keeps the pairs over either threshold as candidates for a
+// reviewer, nothing gets flagged automatically
+
+pub const COSINE_MIN: f32 = 0.95;
+// two swapped words in a short description already drop it to about 0.5
+pub const JACCARD_MIN: f32 = 0.4;
+pub const SCAN_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const PERMUTATIONS: usize = 128;
+// 4 rows each, pairs start sharing a band around JACCARD_MIN
+const BANDS: usize = 32;
+// words per shingle
+const SHINGLE: usize = 3;
+// "a cool game i made" matches every other one, too short to say anything
+const MIN_SHINGLES: usize = 8;
+// embedding neighbours looked at per project
+const NEIGHBOURS: usize = 10;
+const EF: usize = 64;
+// a band bucket this full is template text (default readmes and the like), comparing
+// all of it against itself is quadratic and says nothing
+const MAX_BUCKET: usize = 200;
+// projects per read lock, so /add isnt held up for the whole scan
+const CHUNK: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DuplicatePair {
+    // lower id first
+    pub a: UniqueString,
+    pub b: UniqueString,
+    // null when either isnt embedded or they came from different models
+    pub cosine: Option<f32>,
+    // estimated from minhash, null when either text is too short
+    pub jaccard: Option<f32>,
+    pub same_event: bool,
+    pub same_author: bool,
+    // what pairs are ranked by, the mean of both with a missing one counting as 0
+    pub score: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DuplicateReport {
+    pub scanned_at: u64,
+    pub projects: usize,
+    // best score first
+    pub pairs: Vec<DuplicatePair>,
+}
+
+// minhash signature over the shingles of all the texts, none if there arent enough
+pub fn signature(texts: &[&str]) -> Option<Vec<u64>> {
+    let mut shingles = HashSet::new();
+    for text in texts {
+        let words: Vec<String> = lexical::tokenize(text).collect();
+        for shingle in words.windows(SHINGLE) {
+            let mut hasher = DefaultHasher::new();
+            shingle.hash(&mut hasher);
+            shingles.insert(hasher.finish());
+        }
+    }
+    if shingles.len() < MIN_SHINGLES {
+        return None;
+    }
+    Some(
+        (0..PERMUTATIONS as u64)
+            .map(|seed| {
+                let seed = mix(seed + 1);
+                shingles
+                    .iter()
+                    .map(|&shingle| mix(shingle ^ seed))
+                    .min()
+                    .unwrap_or(u64::MAX)
+            })
+            .collect(),
+    )
+}
+
+// share of the signature that agrees, an estimate of the shingle set jaccard
+pub fn jaccard(a: &[u64], b: &[u64]) -> f32 {
+    let same = a.iter().zip(b).filter(|(a, b)| a == b).count();
+    same as f32 / a.len().max(1) as f32
+}
+
+// splitmix64 finalizer, each seed turns it into a different permutation
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+// what the scan needs from each project, copied out so the lock can be let go
+struct Project {
+    key: UniqueString,
+    event: &'static str,
+    author: String,
+    signature: Option<Vec<u64>>,
+    model: Option<String>,
+}
+
+// clears the running flag however the scan ends, a panic halfway through would
+// otherwise leave every later scan turned away
+struct Running<'a>(&'a AtomicBool);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+pub struct DuplicateFinder {
+    // empty for in memory databases, nothing gets saved
+    location: String,
+    report: Mutex<DuplicateReport>,
+    running: AtomicBool,
+}
+
+impl DuplicateFinder {
+    // kept next to the database as {database}.duplicates.json
+    pub fn open(database: &str) -> DuplicateFinder {
+        let location = match database {
+            "" => String::new(),
+            database => format!("{database}.duplicates.json"),
+        };
+        let report = fs::read(&location)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        DuplicateFinder {
+            location,
+            report: Mutex::new(report),
+            running: AtomicBool::new(false),
+        }
+    }
+    pub fn running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+    // (scanned_at, projects looked at) of the last scan, 0s before the first
+    pub fn scanned(&self) -> (u64, usize) {
+        let report = self.report.lock().unwrap();
+        (report.scanned_at, report.projects)
+    }
+    // total pairs and one page of them, best first
+    pub fn ranked(&self, offset: usize, limit: usize) -> (usize, Vec<DuplicatePair>) {
+        let report = self.report.lock().unwrap();
+        let page = report
+            .pairs
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect();
+        (report.pairs.len(), page)
+    }
+    pub fn pairs_with(&self, key: &UniqueString) -> Vec<DuplicatePair> {
+        let report = self.report.lock().unwrap();
+        report
+            .pairs
+            .iter()
+            .filter(|pair| pair.a == *key || pair.b == *key)
+            .cloned()
+            .collect()
+    }
+    // cpu bound and takes a while on the full archive, run it on a blocking thread.
+    // false if a scan was already going
+    pub fn scan(&self, database: &Database) -> bool {
+        if self.running.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        let _running = Running(&self.running);
+        let length = database.raw_data.read().unwrap().length;
+
+        let mut projects = Vec::with_capacity(length);
+        for start in (0..length).step_by(CHUNK) {
+            let data = database.raw_data.read().unwrap();
+            for i in start..(start + CHUNK).min(length) {
+                let page = &data.raw_text[i];
+                let fields = page.filter_fields();
+                projects.push(Project {
+                    key: page.unique_string(),
+                    event: fields.event,
+                    author: fields.author.to_owned(),
+                    signature: signature(&page.written_text()),
+                    model: data.processed[i]
+                        .as_ref()
+                        .map(|computed| computed.model.clone()),
+                });
+            }
+        }
+
+        // pairs sharing any band of their signature
+        let rows = PERMUTATIONS / BANDS;
+        let mut buckets: HashMap<(usize, u64), Vec<usize>> = HashMap::new();
+        for (i, project) in projects.iter().enumerate() {
+            let Some(signature) = &project.signature else {
+                continue;
+            };
+            for (band, chunk) in signature.chunks(rows).enumerate() {
+                let mut hasher = DefaultHasher::new();
+                chunk.hash(&mut hasher);
+                buckets.entry((band, hasher.finish())).or_default().push(i);
+            }
+        }
+        let mut candidates: HashSet<(usize, usize)> = HashSet::new();
+        for bucket in buckets.values().filter(|bucket| bucket.len() <= MAX_BUCKET) {
+            for (n, &a) in bucket.iter().enumerate() {
+                for &b in &bucket[n + 1..] {
+                    candidates.insert((a.min(b), a.max(b)));
+                }
+            }
+        }
+
+        // plus each project's closest embeddings, and the cosine for every candidate
+        let mut cosines: HashMap<(usize, usize), f32> = HashMap::new();
+        for start in (0..length).step_by(CHUNK) {
+            let data = database.raw_data.read().unwrap();
+            for i in start..(start + CHUNK).min(length) {
+                let Some(computed) = &data.processed[i] else {
+                    continue;
+                };
+                let similarity = |j: usize| match &data.processed[j] {
+                    Some(other) if other.model == computed.model => {
+                        embedder::comparare_cos(&computed.embedding, &other.embedding)
+                    }
+                    _ => -1.0,
+                };
+                for (cosine, j) in data.vectors.search_by(NEIGHBOURS + 1, EF, similarity) {
+                    if j != i && j < length && cosine >= COSINE_MIN {
+                        cosines.insert((i.min(j), i.max(j)), cosine);
+                    }
+                }
+            }
+        }
+        candidates.extend(cosines.keys().copied());
+        let mut candidates: Vec<(usize, usize)> = candidates.into_iter().collect();
+        candidates.sort();
+        for chunk in candidates.chunks(CHUNK) {
+            let data = database.raw_data.read().unwrap();
+            for &(a, b) in chunk {
+                if cosines.contains_key(&(a, b)) || projects[a].model != projects[b].model {
+                    continue;
+                }
+                if let (Some(x), Some(y)) = (&data.processed[a], &data.processed[b]) {
+                    cosines.insert((a, b), embedder::comparare_cos(&x.embedding, &y.embedding));
+                }
+            }
+        }
+
+        let mut pairs: Vec<DuplicatePair> = candidates
+            .into_iter()
+            .filter_map(|(a, b)| {
+                let (x, y) = (&projects[a], &projects[b]);
+                // float error puts identical vectors a hair over 1
+                let cosine = cosines.get(&(a, b)).map(|cosine| cosine.min(1.0));
+                let jaccard = match (&x.signature, &y.signature) {
+                    (Some(x), Some(y)) => Some(jaccard(x, y)),
+                    _ => None,
+                };
+                let over_cosine = cosine.is_some_and(|cosine| cosine >= COSINE_MIN);
+                let over_jaccard = jaccard.is_some_and(|jaccard| jaccard >= JACCARD_MIN);
+                if !over_cosine && !over_jaccard {
+                    return None;
+                }
+                Some(DuplicatePair {
+                    a: x.key.clone(),
+                    b: y.key.clone(),
+                    cosine,
+                    jaccard,
+                    same_event: x.event == y.event,
+                    same_author: !x.author.is_empty() && x.author == y.author,
+                    score: (cosine.unwrap_or(0.0).max(0.0) + jaccard.unwrap_or(0.0)) / 2.0,
+                })
+            })
+            .collect();
+        pairs.sort_by(|x, y| y.score.total_cmp(&x.score));
+
+        let report = DuplicateReport {
+            scanned_at: history::now(),
+            projects: length,
+            pairs,
+        };
+        println!(
+            "duplicate scan: {} candidate pairs in {} projects",
+            report.pairs.len(),
+            length
+        );
+        if !self.location.is_empty() {
+            let saved = serde_json::to_vec(&report)
+                .map_err(std::io::Error::from)
+                .and_then(|bytes| storage::write_atomic(&self.location, &bytes));
+            if let Err(e) = saved {
+                eprintln!("cant save duplicate report: {e}");
+            }
+        }
+        *self.report.lock().unwrap() = report;
+        true
+    }
+}
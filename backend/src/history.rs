@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::data::ScrapedMainPageEnum;
+
+// every scraped version of a project, oldest first. the database keeps the earlier ones
+// in its history and the last one is just what raw_text holds
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Revision {
+    // unix seconds, 0 for entries that existed before history was kept
+    pub scraped_at: u64,
+    pub page: ScrapedMainPageEnum,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FieldChange {
+    // dotted path into the page, list items get their position like updates[3]
+    pub field: String,
+    // null when the field or list item didnt exist on that side
+    pub before: Value,
+    pub after: Value,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RevisionDiff {
+    pub from: usize,
+    pub to: usize,
+    pub from_scraped_at: u64,
+    pub to_scraped_at: u64,
+    pub changes: Vec<FieldChange>,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn diff(revisions: &[Revision], from: usize, to: usize) -> Option<RevisionDiff> {
+    let before = serde_json::to_value(&revisions.get(from)?.page).ok()?;
+    let after = serde_json::to_value(&revisions.get(to)?.page).ok()?;
+
+    let mut changes = vec![];
+    match (single_variant(&before), single_variant(&after)) {
+        // same event on both sides, skip the enum wrapper in the paths
+        (Some((a_name, a)), Some((b_name, b))) if a_name == b_name => {
+            diff_values(String::new(), a, b, &mut changes)
+        }
+        _ => diff_values(String::new(), &before, &after, &mut changes),
+    }
+
+    Some(RevisionDiff {
+        from,
+        to,
+        from_scraped_at: revisions[from].scraped_at,
+        to_scraped_at: revisions[to].scraped_at,
+        changes,
+    })
+}
+
+fn single_variant(value: &Value) -> Option<(&String, &Value)> {
+    let object = value.as_object()?;
+    if object.len() != 1 {
+        return None;
+    }
+    object.iter().next()
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+fn diff_values(path: String, before: &Value, after: &Value, changes: &mut Vec<FieldChange>) {
+    if before == after {
+        return;
+    }
+    match (before, after) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut keys: Vec<&String> = a.keys().chain(b.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let a_value = a.get(key).unwrap_or(&Value::Null);
+                let b_value = b.get(key).unwrap_or(&Value::Null);
+                diff_values(join(&path, key), a_value, b_value, changes);
+            }
+        }
+        // devlogs get removed or added more than edited, so compare lists as sets
+        (Value::Array(a), Value::Array(b)) => {
+            for (i, item) in a.iter().enumerate() {
+                if !b.contains(item) {
+                    changes.push(FieldChange {
+                        field: format!("{path}[{i}]"),
+                        before: item.clone(),
+                        after: Value::Null,
+                    });
+                }
+            }
+            for (i, item) in b.iter().enumerate() {
+                if !a.contains(item) {
+                    changes.push(FieldChange {
+                        field: format!("{path}[{i}]"),
+                        before: Value::Null,
+                        after: item.clone(),
+                    });
+                }
+            }
+        }
+        _ => changes.push(FieldChange {
+            field: path,
+            before: before.clone(),
+            after: after.clone(),
+        }),
+    }
+}
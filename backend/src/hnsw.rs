@@ -0,0 +1,327 @@
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use crate::embedder;
+
+// graph approximate nearest neighbour search, see malkov & yashunin 2016
+// the index only stores links, vectors are looked up from the database by id
+
+type Scored = (OrderedFloat<f32>, u32);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HnswIndex {
+    // max links per node on upper layers, layer 0 gets double
+    m: usize,
+    ef_construction: usize,
+    entry_point: Option<u32>,
+    max_level: usize,
+    // links[node][level] = neighbours of node on that level
+    links: Vec<Vec<Vec<u32>>>,
+}
+
+impl Default for HnswIndex {
+    fn default() -> Self {
+        HnswIndex::new(16, 200)
+    }
+}
+
+impl HnswIndex {
+    pub fn new(m: usize, ef_construction: usize) -> HnswIndex {
+        HnswIndex {
+            m,
+            ef_construction,
+            entry_point: None,
+            max_level: 0,
+            links: vec![],
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+    // level only depends on the id so rebuilding gives the same graph
+    fn random_level(&self, node: u32) -> usize {
+        let mut rng = fastrand::Rng::with_seed(0x5eed_0000 ^ node as u64);
+        let ml = 1.0 / (self.m as f64).ln();
+        let uniform = rng.f64().max(f64::MIN_POSITIVE);
+        (-uniform.ln() * ml).floor() as usize
+    }
+    fn max_links(&self, level: usize) -> usize {
+        if level == 0 { self.m * 2 } else { self.m }
+    }
+
+    // nodes have to be inserted in id order, same as raw_text
+    pub fn insert<'a>(&mut self, node: usize, vector_of: impl Fn(usize) -> &'a [f32]) {
+        assert!(node == self.links.len());
+        let node = node as u32;
+        let level = self.random_level(node);
+        self.links.push(vec![vec![]; level + 1]);
+
+        let Some(entry) = self.entry_point else {
+            self.entry_point = Some(node);
+            self.max_level = level;
+            return;
+        };
+        self.connect(node, level, entry, &vector_of);
+
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry_point = Some(node);
+        }
+    }
+    // placeholder for an entry without a vector yet. nothing links to it so searches
+    // never reach it, update wires it in once the vector exists
+    pub fn insert_unlinked(&mut self, node: usize) {
+        assert!(node == self.links.len());
+        let level = self.random_level(node as u32);
+        self.links.push(vec![vec![]; level + 1]);
+    }
+    // for when a node's vector changed in place, its old links are still used as
+    // paths to find the new neighbours then replaced
+    pub fn update<'a>(&mut self, node: usize, vector_of: impl Fn(usize) -> &'a [f32]) {
+        let node = node as u32;
+        let level = self.links[node as usize].len() - 1;
+        // every node before this one was a placeholder
+        let Some(entry) = self.entry_point else {
+            self.entry_point = Some(node);
+            self.max_level = level;
+            return;
+        };
+        self.connect(node, level, entry, &vector_of);
+
+        // only ever true for placeholders, everything else was counted on insert
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry_point = Some(node);
+        }
+    }
+    fn connect<'a>(
+        &mut self,
+        node: u32,
+        level: usize,
+        mut entry: u32,
+        vector_of: &impl Fn(usize) -> &'a [f32],
+    ) {
+        let query = vector_of(node as usize);
+        let similarity =
+            |other: u32| OrderedFloat(embedder::comparare_cos(query, vector_of(other as usize)));
+
+        for current_level in (level + 1..=self.max_level).rev() {
+            entry = self.greedy_closest(entry, current_level, &similarity);
+        }
+
+        let mut entry_points = vec![entry];
+        for current_level in (0..=level.min(self.max_level)).rev() {
+            let found = self.search_layer(
+                &entry_points,
+                self.ef_construction,
+                current_level,
+                &similarity,
+            );
+            let neighbours: Vec<u32> = found
+                .iter()
+                .map(|x| x.1)
+                .filter(|other| *other != node)
+                .take(self.m)
+                .collect();
+
+            self.links[node as usize][current_level] = neighbours.clone();
+            for neighbour in neighbours {
+                let links = &mut self.links[neighbour as usize][current_level];
+                if !links.contains(&node) {
+                    links.push(node);
+                    self.prune(neighbour, current_level, vector_of);
+                }
+            }
+            entry_points = found.into_iter().map(|x| x.1).collect();
+        }
+    }
+    // keep only the closest links once a node goes over its budget
+    fn prune<'a>(&mut self, node: u32, level: usize, vector_of: &impl Fn(usize) -> &'a [f32]) {
+        let limit = self.max_links(level);
+        let links = &mut self.links[node as usize][level];
+        if links.len() <= limit {
+            return;
+        }
+        let base = vector_of(node as usize);
+        let mut scored: Vec<Scored> = links
+            .iter()
+            .map(|other| {
+                let sim = embedder::comparare_cos(base, vector_of(*other as usize));
+                (OrderedFloat(sim), *other)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.cmp(a));
+        scored.truncate(limit);
+        *links = scored.into_iter().map(|x| x.1).collect();
+    }
+
+    // returns (similarity, id) best first
+    pub fn search<'a>(
+        &self,
+        query: &[f32],
+        k: usize,
+        ef: usize,
+        vector_of: impl Fn(usize) -> &'a [f32],
+    ) -> Vec<(f32, usize)> {
+        self.search_by(k, ef, |other| {
+            embedder::comparare_cos(query, vector_of(other))
+        })
+    }
+    // same walk but with any similarity, used for quantized first passes
+    pub fn search_by(
+        &self,
+        k: usize,
+        ef: usize,
+        similarity: impl Fn(usize) -> f32,
+    ) -> Vec<(f32, usize)> {
+        let Some(mut entry) = self.entry_point else {
+            return vec![];
+        };
+        let similarity = |other: u32| OrderedFloat(similarity(other as usize));
+
+        for current_level in (1..=self.max_level).rev() {
+            entry = self.greedy_closest(entry, current_level, &similarity);
+        }
+        self.search_layer(&[entry], ef.max(k), 0, &similarity)
+            .into_iter()
+            .take(k)
+            .map(|(sim, id)| (sim.0, id as usize))
+            .collect()
+    }
+
+    fn greedy_closest(
+        &self,
+        mut entry: u32,
+        level: usize,
+        similarity: &impl Fn(u32) -> OrderedFloat<f32>,
+    ) -> u32 {
+        let mut best = similarity(entry);
+        loop {
+            let mut changed = false;
+            for &neighbour in &self.links[entry as usize][level] {
+                let sim = similarity(neighbour);
+                if sim > best {
+                    best = sim;
+                    entry = neighbour;
+                    changed = true;
+                }
+            }
+            if !changed {
+                return entry;
+            }
+        }
+    }
+
+    // best first list of up to ef nodes
+    fn search_layer(
+        &self,
+        entry_points: &[u32],
+        ef: usize,
+        level: usize,
+        similarity: &impl Fn(u32) -> OrderedFloat<f32>,
+    ) -> Vec<Scored> {
+        let mut visited = vec![false; self.links.len()];
+        // max heap of nodes to expand, min heap of the current best ef
+        let mut candidates: BinaryHeap<Scored> = BinaryHeap::new();
+        let mut results: BinaryHeap<Reverse<Scored>> = BinaryHeap::new();
+
+        for &entry in entry_points {
+            if visited[entry as usize] {
+                continue;
+            }
+            visited[entry as usize] = true;
+            let scored = (similarity(entry), entry);
+            candidates.push(scored);
+            results.push(Reverse(scored));
+            if results.len() > ef {
+                results.pop();
+            }
+        }
+
+        while let Some((sim, node)) = candidates.pop() {
+            let worst = results.peek().unwrap().0.0;
+            if results.len() >= ef && sim < worst {
+                break;
+            }
+            for &neighbour in &self.links[node as usize][level] {
+                if visited[neighbour as usize] {
+                    continue;
+                }
+                visited[neighbour as usize] = true;
+                let scored = (similarity(neighbour), neighbour);
+                if results.len() < ef || scored.0 > results.peek().unwrap().0.0 {
+                    candidates.push(scored);
+                    results.push(Reverse(scored));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        let mut found: Vec<Scored> = results.into_iter().map(|x| x.0).collect();
+        found.sort_by(|a, b| b.cmp(a));
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedder::HashEmbedder;
+
+    // made up words so the texts share trigrams the way real ones do
+    fn corpus(rng: &mut fastrand::Rng, count: usize, words: (usize, usize)) -> Vec<String> {
+        let vocabulary: Vec<String> = (0..400)
+            .map(|_| (0..rng.usize(3..9)).map(|_| rng.lowercase()).collect())
+            .collect();
+        (0..count)
+            .map(|_| {
+                (0..rng.usize(words.0..words.1))
+                    .map(|_| vocabulary[rng.usize(..vocabulary.len())].as_str())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect()
+    }
+
+    fn exact_top_k(vectors: &[Vec<f32>], query: &[f32], k: usize) -> Vec<usize> {
+        let mut scored: Vec<(f32, usize)> = vectors
+            .iter()
+            .enumerate()
+            .map(|(i, vector)| (embedder::comparare_cos(query, vector), i))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+        scored.into_iter().take(k).map(|(_, i)| i).collect()
+    }
+
+    #[test]
+    fn recall_against_exact_search() {
+        let mut rng = fastrand::Rng::with_seed(7);
+        let embedder = HashEmbedder::new(128);
+        let vectors: Vec<Vec<f32>> = corpus(&mut rng, 3000, (6, 20))
+            .iter()
+            .map(|text| embedder.embed_now(text))
+            .collect();
+        let mut index = HnswIndex::default();
+        for i in 0..vectors.len() {
+            index.insert(i, |other| &vectors[other]);
+        }
+
+        let queries = corpus(&mut rng, 100, (2, 6));
+        let mut hits = 0;
+        for query in &queries {
+            let query = embedder.embed_now(query);
+            let exact = exact_top_k(&vectors, &query, 10);
+            let found = index.search(&query, 10, 200, |other| &vectors[other]);
+            assert_eq!(found.len(), 10);
+            hits += found.iter().filter(|(_, i)| exact.contains(i)).count();
+        }
+        let recall = hits as f32 / (queries.len() * 10) as f32;
+        assert!(recall >= 0.95, "recall@10 was {recall}");
+    }
+}
@@ -0,0 +1,205 @@
+use image::{
+    DynamicImage, ImageReader, Limits, Rgb, RgbImage,
+    codecs::jpeg::JpegEncoder,
+    imageops::{self, FilterType},
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    env, fmt, fs,
+    io::{self, Cursor},
+    path::Path,
+};
+
+use crate::storage;
+
+// project images uploaded by the scraper. files are named after the sha256 of their
+// bytes, so a rescrape uploading the same banner again is a no-op and a url never
+// starts pointing at different bytes, which lets /images be cached forever
+
+// banners are a few hundred kb, anything near this isnt one
+pub const MAX_IMAGE_SIZE: usize = 10 * 1024 * 1024;
+// result cards are 220px wide, this leaves room for hidpi screens
+pub const THUMB_WIDTH: u32 = 440;
+const THUMB_QUALITY: u8 = 80;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageKind {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl ImageKind {
+    // from the bytes only, the filename and content type are whatever the client says.
+    // svg is left out on purpose, its a document that can run scripts
+    pub fn sniff(bytes: &[u8]) -> Option<ImageKind> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageKind::Png)
+        } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(ImageKind::Jpeg)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            Some(ImageKind::Gif)
+        } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            Some(ImageKind::Webp)
+        } else {
+            None
+        }
+    }
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageKind::Png => "png",
+            ImageKind::Jpeg => "jpg",
+            ImageKind::Gif => "gif",
+            ImageKind::Webp => "webp",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    Empty,
+    TooLarge { size: usize },
+    UnknownFormat,
+    Io(io::Error),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::Empty => write!(f, "image is empty"),
+            UploadError::TooLarge { size } => {
+                write!(f, "image is {size} bytes, the limit is {MAX_IMAGE_SIZE}")
+            }
+            UploadError::UnknownFormat => write!(f, "not a png, jpeg, gif or webp"),
+            UploadError::Io(e) => write!(f, "cant store image: {e}"),
+        }
+    }
+}
+
+impl From<io::Error> for UploadError {
+    fn from(e: io::Error) -> Self {
+        UploadError::Io(e)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct StoredImage {
+    pub filename: String,
+    // relative to the backend, what goes in main_image
+    pub url: String,
+    pub kind: ImageKind,
+    pub size: usize,
+    // false when the same bytes were already stored
+    pub created: bool,
+}
+
+// SOM_BACKEND_IMAGE_DIR, shared with the media archive so it can copy uploads
+pub fn upload_dir() -> String {
+    env::var("SOM_BACKEND_IMAGE_DIR").unwrap_or_else(|_| "../images".into())
+}
+
+pub struct ImageStore {
+    dir: String,
+}
+
+impl ImageStore {
+    pub fn new(dir: &str) -> ImageStore {
+        ImageStore {
+            dir: dir.trim_end_matches('/').to_owned(),
+        }
+    }
+    pub fn dir(&self) -> &str {
+        &self.dir
+    }
+    pub fn store(&self, bytes: &[u8]) -> Result<StoredImage, UploadError> {
+        if bytes.is_empty() {
+            return Err(UploadError::Empty);
+        }
+        if bytes.len() > MAX_IMAGE_SIZE {
+            return Err(UploadError::TooLarge { size: bytes.len() });
+        }
+        let kind = ImageKind::sniff(bytes).ok_or(UploadError::UnknownFormat)?;
+        let (filename, created) = store_blob(&self.dir, bytes, kind.extension())?;
+        Ok(StoredImage {
+            url: format!("/images/{filename}"),
+            filename,
+            kind,
+            size: bytes.len(),
+            created,
+        })
+    }
+}
+
+// writes bytes to dir as {sha256}.{extension} unless theyre already there, returns the
+// file name and whether it was new. media.rs keeps its archive the same way
+pub fn store_blob(dir: &str, bytes: &[u8], extension: &str) -> io::Result<(String, bool)> {
+    let hash: String = Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let filename = format!("{hash}.{extension}");
+    let path = format!("{dir}/{filename}");
+
+    if Path::new(&path).exists() {
+        return Ok((filename, false));
+    }
+    fs::create_dir_all(dir)?;
+    match storage::write_atomic(&path, bytes) {
+        Ok(()) => Ok((filename, true)),
+        // two writers of the same bytes racing on the temp file, one of them landed
+        Err(_) if Path::new(&path).exists() => Ok((filename, false)),
+        Err(e) => Err(e),
+    }
+}
+
+// with limits, a few kb of png can claim to be 100k by 100k. gifs give their first frame
+pub fn decode(bytes: &[u8]) -> image::ImageResult<DynamicImage> {
+    let mut reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(16384);
+    limits.max_image_height = Some(16384);
+    limits.max_alloc = Some(256 * 1024 * 1024);
+    reader.limits(limits);
+    reader.decode()
+}
+
+// THUMB_WIDTH wide (smaller images keep their size) with transparent parts on white,
+// what thumbnails and hashes are made from
+pub fn shrink(image: DynamicImage) -> RgbImage {
+    let image = if image.width() > THUMB_WIDTH {
+        // fits the width, the height follows from the aspect ratio
+        image.thumbnail(THUMB_WIDTH, u32::MAX)
+    } else {
+        image
+    };
+    let rgba = image.to_rgba8();
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let over_white = |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+        Rgb([over_white(r), over_white(g), over_white(b)])
+    })
+}
+
+pub fn thumbnail(image: &RgbImage) -> image::ImageResult<Vec<u8>> {
+    let mut out = vec![];
+    JpegEncoder::new_with_quality(&mut out, THUMB_QUALITY).encode_image(image)?;
+    Ok(out)
+}
+
+// 64 bit difference hash, each bit is whether a pixel of a 9x8 grayscale copy is
+// brighter than its right neighbour. rescaling, recompressing and small edits flip only
+// a few bits, compare with bktree::hamming
+pub fn dhash(image: &RgbImage) -> u64 {
+    let small = imageops::resize(&imageops::grayscale(image), 9, 8, FilterType::Triangle);
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0];
+            hash = (hash << 1) | brighter as u64;
+        }
+    }
+    hash
+}
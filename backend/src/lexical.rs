@@ -0,0 +1,170 @@
+use ordered_float::OrderedFloat;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+// standard bm25 constants, nothing tuned yet
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+// anything longer is almost always a hash or base64 junk
+const MAX_TOKEN_LEN: usize = 40;
+
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty() && token.len() <= MAX_TOKEN_LEN)
+        .map(|token| token.to_lowercase())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Posting {
+    doc: usize,
+    // weighted term frequency, fields can count for more than 1 per hit
+    tf: f32,
+}
+
+#[derive(Debug, Default)]
+pub struct InvertedIndex {
+    postings: HashMap<String, Vec<Posting>>,
+    doc_lengths: Vec<f32>,
+    total_length: f32,
+}
+
+impl InvertedIndex {
+    pub fn new() -> InvertedIndex {
+        InvertedIndex::default()
+    }
+    pub fn len(&self) -> usize {
+        self.doc_lengths.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.doc_lengths.is_empty()
+    }
+    // docs are expected to be added in order, same index as raw_text
+    pub fn add_document(&mut self, doc: usize, fields: &[(f32, &str)]) {
+        assert!(doc == self.doc_lengths.len());
+        self.doc_lengths.push(0.0);
+        self.index_fields(doc, fields);
+    }
+    // old_fields has to be what the doc was indexed with, its tokens say which postings to clean
+    pub fn replace_document(
+        &mut self,
+        doc: usize,
+        old_fields: &[(f32, &str)],
+        new_fields: &[(f32, &str)],
+    ) {
+        let mut old_tokens: Vec<String> = old_fields
+            .iter()
+            .flat_map(|(_, text)| tokenize(text))
+            .collect();
+        old_tokens.sort();
+        old_tokens.dedup();
+        for token in old_tokens {
+            if let Some(postings) = self.postings.get_mut(&token) {
+                postings.retain(|posting| posting.doc != doc);
+                if postings.is_empty() {
+                    self.postings.remove(&token);
+                }
+            }
+        }
+        self.total_length -= self.doc_lengths[doc];
+        self.index_fields(doc, new_fields);
+    }
+    fn index_fields(&mut self, doc: usize, fields: &[(f32, &str)]) {
+        let mut frequencies: HashMap<String, f32> = HashMap::new();
+        let mut length = 0.0;
+        for (weight, text) in fields {
+            for token in tokenize(text) {
+                *frequencies.entry(token).or_default() += weight;
+                length += weight;
+            }
+        }
+
+        for (token, tf) in frequencies {
+            self.postings
+                .entry(token)
+                .or_default()
+                .push(Posting { doc, tf });
+        }
+        self.doc_lengths[doc] = length;
+        self.total_length += length;
+    }
+    pub fn search(&self, query: &str, k: usize) -> Vec<(f32, usize)> {
+        self.search_where(query, k, |_| true).0
+    }
+    // same as search but only docs passing allowed can take a spot in the top k,
+    // also returns how many allowed docs matched at all
+    pub fn search_where(
+        &self,
+        query: &str,
+        k: usize,
+        allowed: impl Fn(usize) -> bool,
+    ) -> (Vec<(f32, usize)>, usize) {
+        let scores = self.score_all(query);
+        let mut hits = 0;
+
+        // k comes from the request, the doc count bounds it
+        let mut min_heap: BinaryHeap<Reverse<(OrderedFloat<f32>, usize)>> =
+            BinaryHeap::with_capacity(k.min(scores.len()) + 1);
+        for (doc, score) in scores.into_iter().enumerate() {
+            if score <= 0.0 || !allowed(doc) {
+                continue;
+            }
+            hits += 1;
+            // ties keep the lower doc, same as the final sort, so a bigger k only ever
+            // adds to the end
+            if min_heap.len() < k {
+                min_heap.push(Reverse((OrderedFloat(score), doc)));
+            } else if min_heap
+                .peek()
+                .is_some_and(|Reverse((lowest, _))| OrderedFloat(score) > *lowest)
+            {
+                min_heap.pop();
+                min_heap.push(Reverse((OrderedFloat(score), doc)));
+            }
+        }
+
+        let mut ranked: Vec<(f32, usize)> = min_heap
+            .into_iter()
+            .map(|Reverse((score, doc))| (score.0, doc))
+            .collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        (ranked, hits)
+    }
+    pub fn documents_with(&self, token: &str) -> impl Iterator<Item = usize> + '_ {
+        self.postings
+            .get(token)
+            .into_iter()
+            .flatten()
+            .map(|posting| posting.doc)
+    }
+    // dense scores for every doc, 0.0 means no query term matched
+    pub fn score_all(&self, query: &str) -> Vec<f32> {
+        let mut scores = vec![0.0; self.len()];
+        if self.is_empty() {
+            return scores;
+        }
+
+        let n = self.len() as f32;
+        let avg_length = (self.total_length / n).max(f32::EPSILON);
+
+        let mut terms: Vec<String> = tokenize(query).collect();
+        terms.sort();
+        terms.dedup();
+
+        for term in terms {
+            let Some(postings) = self.postings.get(&term) else {
+                continue;
+            };
+            let df = postings.len() as f32;
+            let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+
+            for posting in postings {
+                let length_norm = 1.0 - B + B * self.doc_lengths[posting.doc] / avg_length;
+                scores[posting.doc] +=
+                    idf * posting.tf * (K1 + 1.0) / (posting.tf + K1 * length_norm);
+            }
+        }
+        scores
+    }
+}
@@ -1,4 +1,20 @@
+pub mod bktree;
+pub mod bulk;
 pub mod data;
 pub mod database;
 pub mod embedder;
+pub mod fraud;
+pub mod history;
+pub mod hnsw;
+pub mod images;
+pub mod lexical;
 pub mod links;
+pub mod media;
+pub mod paging;
+pub mod quantize;
+pub mod query;
+pub mod query_cache;
+pub mod ranking;
+pub mod reembed;
+pub mod storage;
+pub mod wal;
@@ -1,15 +1,32 @@
+pub mod bktree;
+pub mod bulk;
 pub mod data;
 pub mod database;
 pub mod embedder;
+pub mod fraud;
+pub mod history;
+pub mod hnsw;
+pub mod images;
+pub mod lexical;
 pub mod links;
+pub mod media;
+pub mod paging;
+pub mod quantize;
+pub mod query;
+pub mod query_cache;
+pub mod ranking;
+pub mod reembed;
+pub mod storage;
+pub mod wal;
 
 use axum::http::StatusCode;
 use axum::{
     Json, Router,
-    extract::{Query, State},
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, Multipart, Query, State},
     http::{
         HeaderValue, Method,
-        header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+        header::{ACCEPT, AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
     },
     response::{IntoResponse, Response},
     routing::{get, get_service, post},
@@ -22,24 +39,43 @@ use std::{
     time::{Duration, Instant},
 };
 use tokio::{signal, time};
-use tower_http::{cors::CorsLayer, services::ServeDir};
+use tower_http::{cors::CorsLayer, services::ServeDir, set_header::SetResponseHeaderLayer};
 
-use crate::{data::ScrapedMainPageEnum, database::Database};
+use http_body_util::BodyExt;
+
+use crate::{
+    bulk::BulkImport,
+    data::{ScrapedMainPageEnum, UniqueString},
+    database::{AddOutcome, Database, Extras, ExtrasError},
+    fraud::DuplicateFinder,
+    images::{ImageKind, ImageStore, UploadError},
+    paging::{Cursor, Paging},
+    quantize::Quantization,
+    ranking::{Fusion, SearchOptions},
+    reembed::ReembedJob,
+};
 
 struct AppState {
     data: Database,
     secret: String,
+    reembed: ReembedJob,
+    images: ImageStore,
+    duplicates: DuplicateFinder,
+    #[allow(dead_code)]
     start_time: Instant,
 }
 
+#[allow(dead_code)]
 async fn periodic_saves(state: Arc<AppState>) {
     let mut interval = time::interval(Duration::from_secs(15));
     interval.tick().await;
     loop {
         interval.tick().await;
         println!("saving db...");
-        state.data.save_json();
-        println!("completed saving db");
+        match state.data.save() {
+            Ok(()) => println!("completed saving db"),
+            Err(e) => eprintln!("failed saving db: {e}"),
+        }
     }
 }
 
@@ -65,64 +101,291 @@ async fn add_data(
         }
     };
 
-    app_state.data.add_entry(entry).await;
+    let outcome = app_state.data.add_entry(entry).await;
+    let size = app_state.data.raw_data.read().unwrap().length;
 
-    let response_message = format!("Approx size of db: {}", app_state.data.raw_data.read().unwrap().length);
+    // stored either way, accepted means its embedding is still to come
+    let status = match outcome {
+        AddOutcome::Inserted { pending: true, .. } | AddOutcome::Updated { pending: true, .. } => {
+            StatusCode::ACCEPTED
+        }
+        _ => StatusCode::OK,
+    };
+    (
+        status,
+        Json(serde_json::json!({ "result": outcome, "size": size })),
+    )
+        .into_response()
+}
+#[derive(Deserialize, Debug)]
+struct BulkAddRequest {
+    secret: String,
+}
+// body is ndjson, one ScrapedMainPageEnum per line, see bulk.rs
+async fn bulk_add(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<BulkAddRequest>,
+    mut body: Body,
+) -> Response {
+    if payload.secret != app_state.secret {
+        return (StatusCode::UNAUTHORIZED, "Invalid secret".to_string()).into_response();
+    }
+
+    let start = Instant::now();
+    let mut import = BulkImport::new(&app_state.data);
+    while let Some(frame) = body.frame().await {
+        match frame {
+            Ok(frame) => {
+                if let Some(data) = frame.data_ref() {
+                    import.push(data).await;
+                }
+            }
+            Err(e) => {
+                // lines before this point are already in
+                let summary = import.finish().await;
+                eprintln!("bulk upload cut off: {e}");
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({ "error": e.to_string(), "partial": summary })),
+                )
+                    .into_response();
+            }
+        }
+    }
+    let summary = import.finish().await;
+    println!(
+        "bulk add of {} lines took {:?}",
+        summary.results.len(),
+        start.elapsed()
+    );
+    (StatusCode::OK, Json(summary)).into_response()
+}
+
+// the field called file, the rest is ignored
+async fn file_field(multipart: &mut Multipart) -> Result<Bytes, Response> {
+    let field = loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) if field.name() == Some("file") => break field,
+            Ok(Some(_)) => continue,
+            Ok(None) => {
+                return Err((StatusCode::BAD_REQUEST, "no file field".to_string()).into_response());
+            }
+            Err(e) => return Err((e.status(), e.body_text()).into_response()),
+        }
+    };
+    // over the body limit shows up here as a 413
+    field
+        .bytes()
+        .await
+        .map_err(|e| (e.status(), e.body_text()).into_response())
+}
 
-    (StatusCode::OK, response_message).into_response()
+#[derive(Deserialize, Debug)]
+struct UploadImageRequest {
+    secret: String,
 }
+// multipart with the image in a field called file, answers with where it ended up
+async fn upload_image(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<UploadImageRequest>,
+    mut multipart: Multipart,
+) -> Response {
+    if payload.secret != app_state.secret {
+        return (StatusCode::UNAUTHORIZED, "Invalid secret".to_string()).into_response();
+    }
+
+    let bytes = match file_field(&mut multipart).await {
+        Ok(bytes) => bytes,
+        Err(response) => return response,
+    };
+    match app_state.images.store(&bytes) {
+        Ok(stored) => (StatusCode::OK, Json(stored)).into_response(),
+        Err(e @ UploadError::TooLarge { .. }) => {
+            (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()).into_response()
+        }
+        Err(e @ UploadError::UnknownFormat) => {
+            (StatusCode::UNSUPPORTED_MEDIA_TYPE, e.to_string()).into_response()
+        }
+        Err(e @ UploadError::Empty) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        Err(e @ UploadError::Io(_)) => {
+            eprintln!("{e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 struct SearchInputRequest {
     q: String,
+    fusion: Option<Fusion>,
+    // 0 = keywords only, 1 = embeddings only
+    semantic_weight: Option<f32>,
+    rrf_k: Option<f32>,
+    // recall vs latency for the ann index, ignored on small databases
+    ef: Option<usize>,
+    quantization: Option<Quantization>,
+    // page size, capped at paging::MAX_LIMIT
+    limit: Option<usize>,
+    offset: Option<usize>,
+    // next_cursor from the previous page, wins over offset
+    cursor: Option<String>,
+}
+impl SearchInputRequest {
+    fn options(&self) -> SearchOptions {
+        let default = SearchOptions::default();
+        SearchOptions {
+            fusion: self.fusion.unwrap_or(default.fusion),
+            semantic_weight: self
+                .semantic_weight
+                .unwrap_or(default.semantic_weight)
+                .clamp(0.0, 1.0),
+            rrf_k: self.rrf_k.unwrap_or(default.rrf_k).max(1.0),
+            ef_search: self.ef.unwrap_or(default.ef_search).clamp(1, 10_000),
+            quantization: self.quantization.unwrap_or(default.quantization),
+            ..default
+        }
+    }
+    fn paging(&self, options: &SearchOptions) -> Result<Paging, String> {
+        let cursor = match &self.cursor {
+            Some(text) => {
+                let cursor = Cursor::decode(text).ok_or("bad cursor")?;
+                if cursor.fingerprint != paging::fingerprint(&self.q, options) {
+                    return Err("cursor is from a different search".into());
+                }
+                Some(cursor)
+            }
+            None => None,
+        };
+        let mut limit = self
+            .limit
+            .unwrap_or(paging::DEFAULT_LIMIT)
+            .clamp(1, paging::MAX_LIMIT);
+        // the last page a cursor leads to is just shorter
+        if let Some(cursor) = cursor {
+            limit = limit.min(paging::MAX_DEPTH - cursor.depth);
+        }
+        let paging = Paging {
+            limit,
+            offset: self.offset.unwrap_or(0),
+            cursor,
+        };
+        if !paging.in_bounds() {
+            return Err(format!(
+                "results only go {} deep, narrow the search instead",
+                paging::MAX_DEPTH
+            ));
+        }
+        Ok(paging)
+    }
 }
 async fn query_sort(
     State(app_state): State<Arc<AppState>>,
     Query(payload): Query<SearchInputRequest>,
 ) -> Response {
     let db_load_start = Instant::now();
-    let search_results = app_state.data.search_and_rank_json(payload.q, 500).await;
+    let options = payload.options();
+    let paging = match payload.paging(&options) {
+        Ok(paging) => paging,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let search_results = app_state
+        .data
+        .search_and_rank_json(payload.q, &paging, &options)
+        .await;
     println!("sort took: {:?}", db_load_start.elapsed());
-    (StatusCode::OK, search_results).into_response()
+    match search_results {
+        Ok(json) => (StatusCode::OK, json).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("bad query: {e}")).into_response(),
+    }
 }
 
 #[derive(Deserialize, Debug)]
 struct GetPreviewRequest {
-    uuid: usize,
+    // either the id from /query or the unique string (the project url)
+    uuid: Option<usize>,
+    key: Option<String>,
 }
 async fn get_preview(
     State(app_state): State<Arc<AppState>>,
     Query(payload): Query<GetPreviewRequest>,
-) -> impl IntoResponse {
-    // let db_load_start = Instant::now();
-    // let data_guard = app_state.data.raw_data.read().unwrap();
-    // if payload.uuid >= data_guard.length {
-    //     return (StatusCode::NOT_FOUND, "UUID not found".to_string()).into_response();
-    // }
-    // let page = &data_guard.raw_text[payload.uuid].clone();
-    // drop(data_guard);
-
-    // match serde_json::to_string(page) {
-    //     Ok(json) => {
-    //         println!("loaded preview in: {:?}", db_load_start.elapsed());
-    //         (StatusCode::OK, json).into_response()
-    //     }
-    //     Err(e) => {
-    //         eprintln!("Failed to serialize page preview: {}", e);
-    //         (
-    //             StatusCode::INTERNAL_SERVER_ERROR,
-    //             "Failed to serialize preview".to_string(),
-    //         )
-    //             .into_response()
-    //     }
-    // }
+) -> Response {
+    let db_load_start = Instant::now();
+    let index = match (payload.key, payload.uuid) {
+        (Some(key), _) => app_state.data.index_for(&UniqueString(key)),
+        (None, uuid) => uuid,
+    };
+    match index.and_then(|index| app_state.data.details(index)) {
+        Some(details) => {
+            println!("loaded preview in: {:?}", db_load_start.elapsed());
+            (StatusCode::OK, Json(details)).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "ID not found".to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct HistoryRequest {
+    // either the position in the db or the unique string (the url for summer)
+    uuid: Option<usize>,
+    key: Option<String>,
+    // revision positions for /history/diff, defaults to first vs latest
+    from: Option<usize>,
+    to: Option<usize>,
+}
+impl HistoryRequest {
+    fn unique_string(&self, database: &Database) -> Option<UniqueString> {
+        match (&self.key, self.uuid) {
+            (Some(key), _) => Some(UniqueString(key.clone())),
+            (None, Some(uuid)) => database.key_for(uuid),
+            (None, None) => None,
+        }
+    }
+}
+async fn get_history(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<HistoryRequest>,
+) -> Response {
+    let history = payload
+        .unique_string(&app_state.data)
+        .and_then(|key| app_state.data.history(&key));
+    match history {
+        Some(revisions) => (StatusCode::OK, Json(revisions)).into_response(),
+        None => (StatusCode::NOT_FOUND, "ID not found".to_string()).into_response(),
+    }
+}
+async fn get_history_diff(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<HistoryRequest>,
+) -> Response {
+    let Some(key) = payload.unique_string(&app_state.data) else {
+        return (StatusCode::NOT_FOUND, "ID not found".to_string()).into_response();
+    };
+    let Some(revisions) = app_state.data.history(&key) else {
+        return (StatusCode::NOT_FOUND, "ID not found".to_string()).into_response();
+    };
+    let from = payload.from.unwrap_or(0);
+    let to = payload.to.unwrap_or(revisions.len().saturating_sub(1));
+    match app_state.data.revision_diff(&key, from, to) {
+        Some(diff) => (StatusCode::OK, Json(diff)).into_response(),
+        None => (
+            StatusCode::BAD_REQUEST,
+            format!("revisions go from 0 to {}", revisions.len() - 1),
+        )
+            .into_response(),
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 struct SetExtrasRequest {
     secret: String,
     id: usize,
+    ai_description: Option<f32>,
+    ai_code: Option<f32>,
     score_multiplier: Option<f32>,
+    // replaces the stored one, has to be 768 wide
     embedding: Option<Vec<f32>>,
+    // model that made embedding, defaults to the one the server runs with
+    embedding_model: Option<String>,
 }
 async fn set_extras(
     State(app_state): State<Arc<AppState>>,
@@ -132,36 +395,359 @@ async fn set_extras(
         return (StatusCode::UNAUTHORIZED, "Invalid secret".to_string()).into_response();
     }
 
-    // if payload.id >= app_state.data.raw_data.read().unwrap().length {
-    //     return (StatusCode::NOT_FOUND, "ID not found".to_string()).into_response();
-    // }
+    let extras = Extras {
+        ai_description: payload.ai_description,
+        ai_code: payload.ai_code,
+        score_multiplier: payload.score_multiplier,
+        embedding: payload.embedding,
+        model: payload.embedding_model,
+    };
+    match app_state.data.set_extras(payload.id, extras) {
+        Ok(computed) => (StatusCode::OK, Json(computed.scores())).into_response(),
+        Err(ExtrasError::NotFound) => {
+            (StatusCode::NOT_FOUND, "ID not found".to_string()).into_response()
+        }
+        Err(ExtrasError::NotEmbedded) => {
+            (StatusCode::CONFLICT, ExtrasError::NotEmbedded.to_string()).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
 
-    todo!()
+// anything embedded by an older model gets redone in the background on startup
+async fn reembed_stale(state: Arc<AppState>) {
+    state.reembed.run(&state.data).await;
+}
+
+async fn retry_pending(state: Arc<AppState>) {
+    reembed::retry_pending(&state.data).await;
+}
+
+async fn archive_media(state: Arc<AppState>) {
+    media::archive_media(&state.data).await;
+}
+
+#[derive(Deserialize, Debug)]
+struct AdminRequest {
+    secret: String,
+}
+async fn reembed_status(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<AdminRequest>,
+) -> Response {
+    if payload.secret != app_state.secret {
+        return (StatusCode::UNAUTHORIZED, "Invalid secret".to_string()).into_response();
+    }
+    (
+        StatusCode::OK,
+        Json(app_state.reembed.status(&app_state.data)),
+    )
+        .into_response()
+}
+// kicks off another run, for entries that failed last time
+async fn reembed_start(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<AdminRequest>,
+) -> Response {
+    if payload.secret != app_state.secret {
+        return (StatusCode::UNAUTHORIZED, "Invalid secret".to_string()).into_response();
+    }
+    if app_state.reembed.progress().running {
+        return (StatusCode::CONFLICT, "re-embed already running".to_string()).into_response();
+    }
+    if app_state.data.stale_embeddings().is_empty() {
+        return (StatusCode::OK, "nothing to re-embed".to_string()).into_response();
+    }
+    tokio::spawn(reembed_stale(Arc::clone(&app_state)));
+    (StatusCode::ACCEPTED, "re-embed started".to_string()).into_response()
+}
+
+#[derive(Deserialize, Debug)]
+struct PendingRequest {
+    secret: String,
+    // longest waiting first, defaults to 1000
+    limit: Option<usize>,
+}
+// entries the embedder still owes a vector, see reembed::retry_pending
+async fn pending_report(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<PendingRequest>,
+) -> Response {
+    if payload.secret != app_state.secret {
+        return (StatusCode::UNAUTHORIZED, "Invalid secret".to_string()).into_response();
+    }
+    let mut entries = app_state.data.pending_report();
+    let total = entries.len();
+    entries.truncate(payload.limit.unwrap_or(1000));
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({ "total": total, "entries": entries })),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize, Debug)]
+struct SimilarImagesRequest {
+    secret: String,
+    // the project to check, same as /history. not needed when posting an image
+    uuid: Option<usize>,
+    key: Option<String>,
+    // most dhash bits out of 64 that can differ, defaults to 10
+    distance: Option<u32>,
+}
+impl SimilarImagesRequest {
+    fn max_distance(&self) -> u32 {
+        self.distance.unwrap_or(10).min(32)
+    }
+}
+// projects whose archived images nearly match the given project's, for spotting copied
+// screenshots
+async fn similar_to_project(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<SimilarImagesRequest>,
+) -> Response {
+    if payload.secret != app_state.secret {
+        return (StatusCode::UNAUTHORIZED, "Invalid secret".to_string()).into_response();
+    }
+    let key = match (&payload.key, payload.uuid) {
+        (Some(key), _) => Some(UniqueString(key.clone())),
+        (None, Some(uuid)) => app_state.data.key_for(uuid),
+        (None, None) => None,
+    };
+    let Some(key) = key.filter(|key| app_state.data.index_for(key).is_some()) else {
+        return (StatusCode::NOT_FOUND, "ID not found".to_string()).into_response();
+    };
+    let hashes = app_state.data.media.hashes_for(&key);
+    let matches = app_state
+        .data
+        .similar_images(&hashes, payload.max_distance(), Some(&key));
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({ "hashes": hashes.len(), "matches": matches })),
+    )
+        .into_response()
+}
+// same for an image that isnt in the archive, multipart with it in a field called file
+async fn similar_to_upload(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<SimilarImagesRequest>,
+    mut multipart: Multipart,
+) -> Response {
+    if payload.secret != app_state.secret {
+        return (StatusCode::UNAUTHORIZED, "Invalid secret".to_string()).into_response();
+    }
+    let bytes = match file_field(&mut multipart).await {
+        Ok(bytes) => bytes,
+        Err(response) => return response,
+    };
+    if ImageKind::sniff(&bytes).is_none() {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            UploadError::UnknownFormat.to_string(),
+        )
+            .into_response();
+    }
+    let hash = tokio::task::spawn_blocking(move || {
+        images::decode(&bytes).map(|image| images::dhash(&images::shrink(image)))
+    })
+    .await;
+    let hash = match hash {
+        Ok(Ok(hash)) => hash,
+        Ok(Err(e)) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let matches = app_state
+        .data
+        .similar_images(&[hash], payload.max_distance(), None);
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({ "hash": format!("{hash:016x}"), "matches": matches })),
+    )
+        .into_response()
+}
+
+// never returns, the first scan runs at startup
+async fn find_duplicates(state: Arc<AppState>) {
+    let mut interval = time::interval(fraud::SCAN_INTERVAL);
+    loop {
+        interval.tick().await;
+        let state = Arc::clone(&state);
+        let scan = tokio::task::spawn_blocking(move || state.duplicates.scan(&state.data));
+        if let Err(e) = scan.await {
+            eprintln!("duplicate scan crashed: {e}");
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct DuplicatesRequest {
+    secret: String,
+    // the project, by position in the db or unique string
+    id: Option<usize>,
+    key: Option<String>,
+}
+// candidate pairs one project is part of, see fraud.rs
+async fn duplicates_of(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<DuplicatesRequest>,
+) -> Response {
+    if payload.secret != app_state.secret {
+        return (StatusCode::UNAUTHORIZED, "Invalid secret".to_string()).into_response();
+    }
+    let key = match (payload.key, payload.id) {
+        (Some(key), _) => Some(UniqueString(key)),
+        (None, Some(id)) => app_state.data.key_for(id),
+        (None, None) => None,
+    };
+    let Some((key, id)) = key.and_then(|key| Some((key.clone(), app_state.data.index_for(&key)?)))
+    else {
+        return (StatusCode::NOT_FOUND, "ID not found".to_string()).into_response();
+    };
+    let (scanned_at, _) = app_state.duplicates.scanned();
+    let pairs = app_state
+        .data
+        .describe_duplicates(app_state.duplicates.pairs_with(&key));
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({ "id": id, "scanned_at": scanned_at, "pairs": pairs })),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize, Debug)]
+struct RankedDuplicatesRequest {
+    secret: String,
+    // defaults to 100, at most 1000
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+// every candidate pair, most suspicious first
+async fn duplicates_ranked(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<RankedDuplicatesRequest>,
+) -> Response {
+    if payload.secret != app_state.secret {
+        return (StatusCode::UNAUTHORIZED, "Invalid secret".to_string()).into_response();
+    }
+    let (scanned_at, projects) = app_state.duplicates.scanned();
+    let (total, pairs) = app_state.duplicates.ranked(
+        payload.offset.unwrap_or(0),
+        payload.limit.unwrap_or(100).min(1000),
+    );
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "scanned_at": scanned_at,
+            "projects": projects,
+            "total": total,
+            "pairs": app_state.data.describe_duplicates(pairs),
+        })),
+    )
+        .into_response()
+}
+// runs a scan now instead of waiting for the next one
+async fn duplicates_scan(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<AdminRequest>,
+) -> Response {
+    if payload.secret != app_state.secret {
+        return (StatusCode::UNAUTHORIZED, "Invalid secret".to_string()).into_response();
+    }
+    if app_state.duplicates.running() {
+        return (StatusCode::CONFLICT, "scan already running".to_string()).into_response();
+    }
+    let state = Arc::clone(&app_state);
+    tokio::task::spawn_blocking(move || state.duplicates.scan(&state.data));
+    (StatusCode::ACCEPTED, "scan started".to_string()).into_response()
+}
+
+async fn media_stats(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<AdminRequest>,
+) -> Response {
+    if payload.secret != app_state.secret {
+        return (StatusCode::UNAUTHORIZED, "Invalid secret".to_string()).into_response();
+    }
+    (StatusCode::OK, Json(app_state.data.media.stats())).into_response()
+}
+// deletes archived files no entry links to anymore
+async fn media_gc(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<AdminRequest>,
+) -> Response {
+    if payload.secret != app_state.secret {
+        return (StatusCode::UNAUTHORIZED, "Invalid secret".to_string()).into_response();
+    }
+    match app_state.data.media.gc() {
+        Ok(summary) => (StatusCode::OK, Json(summary)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// files named by their hash never change, so found ones are cached for good. misses
+// arent cached since the file can still show up
+fn content_addressed(dir: &str) -> Router<Arc<AppState>> {
+    Router::new()
+        .fallback_service(ServeDir::new(dir))
+        .layer(SetResponseHeaderLayer::overriding(
+            CACHE_CONTROL,
+            |response: &Response| {
+                response
+                    .status()
+                    .is_success()
+                    .then(|| HeaderValue::from_static("public, max-age=31536000, immutable"))
+            },
+        ))
+        .layer(SetResponseHeaderLayer::overriding(
+            X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        ))
+}
+
+async fn query_cache_stats(
+    State(app_state): State<Arc<AppState>>,
+    Query(payload): Query<AdminRequest>,
+) -> Response {
+    if payload.secret != app_state.secret {
+        return (StatusCode::UNAUTHORIZED, "Invalid secret".to_string()).into_response();
+    }
+    (StatusCode::OK, Json(app_state.data.query_cache.stats())).into_response()
 }
 
 async fn simple_debug(
     State(app_state): State<Arc<AppState>>,
 ) -> impl IntoResponse {
-    format!("{:?}", app_state.data.ollama.generate(&"test".into()).await.unwrap()[0])
+    let embedder = &app_state.data.embedder;
+    match embedder.embed("test").await {
+        Ok(embed) => format!("{}: {:?}", embedder.model(), embed),
+        Err(e) => format!("{}: {e}", embedder.model()),
+    }
 }
 
 async fn force_save(State(app_state): State<Arc<AppState>>,) -> impl IntoResponse {
-    app_state.data.save_json();
-    "finish"
+    match app_state.data.save() {
+        Ok(()) => "finish".to_string(),
+        Err(e) => format!("failed: {e}"),
+    }
 }
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt().init();
-    let database = Database::load_file("../complete_database.json");
+    let database = Database::load_file("../complete_database.bin");
 
     let secret = env::var("SOM_BACKEND_AUTH_SECRET").unwrap_or_else(|_| {
         eprintln!("no secret is an oops in prod, using default.");
         "not_a_secret_secret".into()
     });
+    let image_dir = images::upload_dir();
+    let duplicates = DuplicateFinder::open(database.file_location);
     let state = Arc::new(AppState {
         data: database,
         secret,
+        reembed: ReembedJob::default(),
+        images: ImageStore::new(&image_dir),
+        duplicates,
         start_time: Instant::now(),
     });
 
@@ -185,20 +771,51 @@ async fn main() {
             )),
         )
         .route("/add", post(add_data))
+        .route("/bulk_add", post(bulk_add))
+        .route(
+            "/upload_image",
+            // room for the multipart framing around the image
+            post(upload_image).layer(DefaultBodyLimit::max(images::MAX_IMAGE_SIZE + 64 * 1024)),
+        )
+        .nest("/images", content_addressed(&image_dir))
+        .nest("/media", content_addressed(state.data.media.dir()))
         .route("/query", get(query_sort))
         .route("/preview", get(get_preview))
+        .route("/history", get(get_history))
+        .route("/history/diff", get(get_history_diff))
         .route("/set_extras", post(set_extras))
+        .route("/admin/reembed", get(reembed_status).post(reembed_start))
+        .route("/admin/query_cache", get(query_cache_stats))
+        .route("/admin/pending", get(pending_report))
+        .route("/duplicates", get(duplicates_of))
+        .route("/duplicates/ranked", get(duplicates_ranked))
+        .route("/admin/duplicates/scan", post(duplicates_scan))
+        .route("/admin/media", get(media_stats))
+        .route("/admin/media/gc", post(media_gc))
+        .route(
+            "/admin/similar_images",
+            get(similar_to_project)
+                .post(similar_to_upload)
+                .layer(DefaultBodyLimit::max(images::MAX_IMAGE_SIZE + 64 * 1024)),
+        )
         .route("/self-debug", get(simple_debug))
         .route("/force-save", get(force_save))
         .with_state(Arc::clone(&state))
         .layer(cors_layer);
 
     // tokio::spawn(periodic_saves(Arc::clone(&state)));
+    tokio::spawn(reembed_stale(Arc::clone(&state)));
+    tokio::spawn(retry_pending(Arc::clone(&state)));
+    tokio::spawn(archive_media(Arc::clone(&state)));
+    tokio::spawn(find_duplicates(Arc::clone(&state)));
 
     tokio::spawn(async move {
         signal::ctrl_c().await.expect("failed to listen for ctrl_c");
         println!("\ntrying to close, saving state...");
-        state.data.save_json();
+        if let Err(e) = state.data.save() {
+            eprintln!("failed saving db, the wal still has recent entries: {e}");
+            exit(1)
+        }
         exit(0)
     });
 
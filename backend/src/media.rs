@@ -0,0 +1,681 @@
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs, io, iter,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::task::JoinSet;
+
+use crate::{
+    bktree::BkTree,
+    data::{DatabasePage, GenericPreviewSearchData, UniqueString},
+    database::Database,
+    history,
+    images::{self, ImageKind},
+    storage,
+};
+
+// copies of every image and attachment entries link to, events take theirs down after
+// they end just like the projects. each blob is stored once by sha256 (see
+// images::store_blob) no matter how many urls or projects point at it. the index (the
+// folder name plus .json, outside so /media doesnt serve it) maps source urls to blobs
+// and counts how many entries use each one, now or in an older revision. blobs nothing uses stay
+// until /admin/media/gc. images also get a small jpeg next to them for result grids and
+// a perceptual hash (images::dhash) so copied screenshots can be found, see similar
+
+// attachments can be videos, anything bigger is skipped
+pub const MAX_MEDIA_SIZE: usize = 50 * 1024 * 1024;
+pub const ARCHIVE_INTERVAL: Duration = Duration::from_secs(300);
+// downloads running at once
+const CONCURRENCY: usize = 8;
+// failed urls wait 5 minutes, doubling up to a day
+const RETRY_BASE: u64 = 300;
+const RETRY_MAX: u64 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Blob {
+    pub size: usize,
+    // entries linking to it now or in an older revision, an entry linking twice counts once
+    pub refs: usize,
+    pub archived_at: u64,
+    // file name of the thumbnail, none for non images and ones that didnt decode
+    #[serde(default)]
+    pub thumb: Option<String>,
+    #[serde(default)]
+    pub dhash: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct FailedFetch {
+    attempts: u32,
+    last_attempt: u64,
+    error: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct MediaIndex {
+    // source url -> blob file name
+    sources: HashMap<String, String>,
+    blobs: HashMap<String, Blob>,
+    // what each entry links to now or ever did in its history, refs are counted from this.
+    // an image edited out of a project is kept, its often what a fraud review is after
+    entries: HashMap<UniqueString, Vec<String>>,
+    failed: HashMap<String, FailedFetch>,
+    // blobs that didnt decode since startup, not tried again until a restart
+    #[serde(skip)]
+    decode_failed: HashSet<String>,
+    // blob names by dhash, rebuilt from blobs on load
+    #[serde(skip)]
+    hashes: BkTree<String>,
+}
+
+impl MediaIndex {
+    fn rebuild_hashes(&mut self) {
+        self.hashes = BkTree::default();
+        for (name, blob) in &self.blobs {
+            if let Some(hash) = blob.dhash {
+                self.hashes.insert(hash, name.clone());
+            }
+        }
+    }
+    fn recount(&mut self) {
+        for blob in self.blobs.values_mut() {
+            blob.refs = 0;
+        }
+        for urls in self.entries.values() {
+            let blobs: HashSet<&String> = urls
+                .iter()
+                .filter_map(|url| self.sources.get(url))
+                .collect();
+            for name in blobs {
+                if let Some(blob) = self.blobs.get_mut(name) {
+                    blob.refs += 1;
+                }
+            }
+        }
+    }
+    // linked from some entry, not archived and not waiting out a failure
+    fn due(&self, now: u64) -> Vec<String> {
+        let mut due: Vec<String> = self
+            .entries
+            .values()
+            .flatten()
+            .filter(|url| !self.sources.contains_key(*url))
+            .filter(|url| match self.failed.get(*url) {
+                Some(failed) => {
+                    let wait = RETRY_BASE
+                        .saturating_mul(1 << failed.attempts.min(16))
+                        .min(RETRY_MAX);
+                    now >= failed.last_attempt + wait
+                }
+                None => true,
+            })
+            .cloned()
+            .collect();
+        due.sort();
+        due.dedup();
+        due
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct MediaStats {
+    pub blobs: usize,
+    pub bytes: usize,
+    pub sources: usize,
+    // linked but not archived yet
+    pub waiting: usize,
+    pub failed: usize,
+    // blobs no current entry links to, what gc would remove
+    pub orphaned: usize,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct GcSummary {
+    pub removed: usize,
+    pub bytes: usize,
+}
+
+// an archived image that looks like one being checked, see MediaArchive::similar
+#[derive(Debug)]
+pub struct ImageMatch {
+    pub key: UniqueString,
+    // the archived copy and where it came from
+    pub blob: String,
+    pub source: String,
+    // bits that differ between the two dhashes, 0 is the same picture
+    pub distance: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct ArchivePass {
+    pub fetched: usize,
+    pub failed: usize,
+    pub thumbnails: usize,
+}
+
+pub struct MediaArchive {
+    // empty for in memory databases, nothing is fetched or written
+    dir: String,
+    // where /images serves uploads from, links to those are copied instead of fetched
+    uploads: String,
+    index: Mutex<MediaIndex>,
+    client: reqwest::Client,
+}
+
+impl MediaArchive {
+    pub fn disabled() -> MediaArchive {
+        MediaArchive {
+            dir: String::new(),
+            uploads: String::new(),
+            index: Mutex::new(MediaIndex::default()),
+            client: client(),
+        }
+    }
+    pub fn open(dir: &str, uploads: &str) -> MediaArchive {
+        let dir = dir.trim_end_matches('/').to_owned();
+        let mut index: MediaIndex = match fs::read(format!("{dir}.json")) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                // the blobs are still there, refetching finds them again
+                eprintln!("bad media index, starting over: {e}");
+                MediaIndex::default()
+            }),
+            Err(_) => MediaIndex::default(),
+        };
+        index.rebuild_hashes();
+        MediaArchive {
+            dir,
+            uploads: uploads.trim_end_matches('/').to_owned(),
+            index: Mutex::new(index),
+            client: client(),
+        }
+    }
+    pub fn from_env() -> MediaArchive {
+        Self::open(
+            &env::var("SOM_BACKEND_MEDIA_DIR").unwrap_or_else(|_| "../media".into()),
+            &images::upload_dir(),
+        )
+    }
+    pub fn dir(&self) -> &str {
+        &self.dir
+    }
+    // the image and its thumbnail for a result card, untouched if not archived yet
+    pub fn rewrite_preview(&self, preview: &mut GenericPreviewSearchData) {
+        let index = self.index.lock().unwrap();
+        let Some(name) = index.sources.get(&preview.img) else {
+            return;
+        };
+        preview.thumb = index.blobs[name]
+            .thumb
+            .as_ref()
+            .map(|thumb| format!("/media/{thumb}"));
+        preview.img = format!("/media/{name}");
+    }
+    // points every link on the page that has been archived at the copy
+    pub fn rewrite(&self, page: &mut impl DatabasePage) {
+        let index = self.index.lock().unwrap();
+        for url in page.media_mut() {
+            if let Some(name) = index.sources.get(url.as_str()) {
+                *url = format!("/media/{name}");
+            }
+        }
+    }
+    pub fn stats(&self) -> MediaStats {
+        let mut index = self.index.lock().unwrap();
+        index.recount();
+        MediaStats {
+            blobs: index.blobs.len(),
+            bytes: index.blobs.values().map(|blob| blob.size).sum(),
+            sources: index.sources.len(),
+            waiting: index.due(u64::MAX).len(),
+            failed: index.failed.len(),
+            orphaned: index.blobs.values().filter(|blob| blob.refs == 0).count(),
+        }
+    }
+    // removes blobs no current entry links to. they were archived for a reason, so this
+    // only runs when asked
+    pub fn gc(&self) -> io::Result<GcSummary> {
+        let mut index = self.index.lock().unwrap();
+        index.recount();
+        let orphaned: Vec<String> = index
+            .blobs
+            .iter()
+            .filter(|(_, blob)| blob.refs == 0)
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        let mut summary = GcSummary::default();
+        for name in orphaned {
+            let thumb = index.blobs[&name].thumb.clone();
+            for file in iter::once(&name).chain(thumb.as_ref()) {
+                match fs::remove_file(format!("{}/{file}", self.dir)) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            if let Some(blob) = index.blobs.remove(&name) {
+                summary.removed += 1;
+                summary.bytes += blob.size;
+            }
+            index.sources.retain(|_, blob| *blob != name);
+        }
+        index.rebuild_hashes();
+        self.save_index(&index)?;
+        Ok(summary)
+    }
+    fn save_index(&self, index: &MediaIndex) -> io::Result<()> {
+        if self.dir.is_empty() {
+            return Ok(());
+        }
+        storage::write_atomic(&format!("{}.json", self.dir), &serde_json::to_vec(index)?)
+    }
+    // takes in what every entry links to now and downloads whatever isnt archived yet
+    pub async fn archive(&self, database: &Database) -> ArchivePass {
+        let mut pass = ArchivePass::default();
+        if self.dir.is_empty() {
+            return pass;
+        }
+
+        let links: HashMap<UniqueString, Vec<String>> = {
+            let data = database.raw_data.read().unwrap();
+            let revisions = data.history.iter().flat_map(|(key, revisions)| {
+                revisions
+                    .iter()
+                    .map(move |revision| (key.clone(), &revision.page))
+            });
+            let mut links: HashMap<UniqueString, Vec<String>> = HashMap::new();
+            for (key, page) in data
+                .raw_text
+                .iter()
+                .map(|page| (page.unique_string(), page))
+                .chain(revisions)
+            {
+                let urls = links.entry(key).or_default();
+                for url in page.media() {
+                    let fetchable = url.starts_with("http://")
+                        || url.starts_with("https://")
+                        || url.starts_with("/images/");
+                    if fetchable && !urls.iter().any(|known| known == url) {
+                        urls.push(url.to_owned());
+                    }
+                }
+            }
+            links
+        };
+        let due = {
+            let mut index = self.index.lock().unwrap();
+            index.entries = links;
+            index.due(history::now())
+        };
+
+        for chunk in due.chunks(CONCURRENCY) {
+            let mut downloads = JoinSet::new();
+            for url in chunk {
+                if let Some(bytes) = self.uploaded(url) {
+                    match self.store(url, &bytes) {
+                        Ok(()) => pass.fetched += 1,
+                        Err(e) => eprintln!("cant archive upload {url}: {e}"),
+                    }
+                    continue;
+                }
+                let client = self.client.clone();
+                let url = url.clone();
+                downloads.spawn(async move {
+                    let result = download(&client, &url).await;
+                    (url, result)
+                });
+            }
+            while let Some(joined) = downloads.join_next().await {
+                let Ok((url, result)) = joined else { continue };
+                match result.and_then(|bytes| self.store(&url, &bytes)) {
+                    Ok(()) => pass.fetched += 1,
+                    Err(e) => {
+                        pass.failed += 1;
+                        let mut index = self.index.lock().unwrap();
+                        let failed = index.failed.entry(url).or_insert(FailedFetch {
+                            attempts: 0,
+                            last_attempt: 0,
+                            error: String::new(),
+                        });
+                        failed.attempts += 1;
+                        failed.last_attempt = history::now();
+                        failed.error = e;
+                    }
+                }
+            }
+        }
+        // what was just downloaded, plus anything from before thumbnails and hashes
+        pass.thumbnails = self.process_images().await;
+
+        let mut index = self.index.lock().unwrap();
+        index.recount();
+        if let Err(e) = self.save_index(&index) {
+            eprintln!("cant save media index: {e}");
+        }
+        pass
+    }
+    // the bytes behind one of our own /images links, on this host or any other. the name
+    // is the sha256 of the file so any copy with it has the same bytes
+    fn uploaded(&self, url: &str) -> Option<Vec<u8>> {
+        if self.uploads.is_empty() {
+            return None;
+        }
+        let path = match url.strip_prefix("/images/") {
+            Some(name) => name.to_owned(),
+            None => reqwest::Url::parse(url)
+                .ok()?
+                .path()
+                .strip_prefix("/images/")?
+                .to_owned(),
+        };
+        let (hash, extension) = path.split_once('.')?;
+        let content_addressed = hash.len() == 64
+            && hash
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+            && extension.bytes().all(|b| b.is_ascii_alphanumeric());
+        if !content_addressed {
+            return None;
+        }
+        fs::read(format!("{}/{path}", self.uploads)).ok()
+    }
+    fn store(&self, url: &str, bytes: &[u8]) -> Result<(), String> {
+        // anything that isnt a known image is served as a download, see /media in main
+        let extension = ImageKind::sniff(bytes).map_or("bin", |kind| kind.extension());
+        let (name, _) =
+            images::store_blob(&self.dir, bytes, extension).map_err(|e| e.to_string())?;
+
+        let mut index = self.index.lock().unwrap();
+        index.failed.remove(url);
+        index.sources.insert(url.to_owned(), name.clone());
+        index.blobs.entry(name).or_insert(Blob {
+            size: bytes.len(),
+            refs: 0,
+            archived_at: history::now(),
+            thumb: None,
+            dhash: None,
+        });
+        Ok(())
+    }
+    // thumbnails and dhashes for images that dont have them yet
+    async fn process_images(&self) -> usize {
+        let todo: Vec<String> = {
+            let index = self.index.lock().unwrap();
+            index
+                .blobs
+                .iter()
+                .filter(|(name, blob)| {
+                    (blob.thumb.is_none() || blob.dhash.is_none())
+                        && !name.ends_with(".bin")
+                        && !index.decode_failed.contains(*name)
+                })
+                .map(|(name, _)| name.clone())
+                .collect()
+        };
+
+        let mut made = 0;
+        for name in todo {
+            let dir = self.dir.clone();
+            let blob = name.clone();
+            // decoding is cpu bound, keep it off the runtime
+            let result = tokio::task::spawn_blocking(move || -> Result<(String, u64), String> {
+                let bytes = fs::read(format!("{dir}/{blob}")).map_err(|e| e.to_string())?;
+                let image = images::decode(&bytes).map_err(|e| e.to_string())?;
+                let small = images::shrink(image);
+                let thumb = images::thumbnail(&small).map_err(|e| e.to_string())?;
+                let stem = blob.split_once('.').map_or(blob.as_str(), |(stem, _)| stem);
+                let file = format!("{stem}.thumb.jpg");
+                storage::write_atomic(&format!("{dir}/{file}"), &thumb)
+                    .map_err(|e| e.to_string())?;
+                Ok((file, images::dhash(&small)))
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+
+            let mut index = self.index.lock().unwrap();
+            match result {
+                Ok((file, hash)) => {
+                    if let Some(blob) = index.blobs.get_mut(&name) {
+                        blob.thumb = Some(file);
+                        if blob.dhash.is_none() {
+                            blob.dhash = Some(hash);
+                            index.hashes.insert(hash, name);
+                        }
+                        made += 1;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("cant make thumbnail for {name}: {e}");
+                    index.decode_failed.insert(name);
+                }
+            }
+        }
+        made
+    }
+    // dhashes of the images an entry links to, empty until theyre archived
+    pub fn hashes_for(&self, key: &UniqueString) -> Vec<u64> {
+        let index = self.index.lock().unwrap();
+        let Some(urls) = index.entries.get(key) else {
+            return vec![];
+        };
+        let mut hashes: Vec<u64> = urls
+            .iter()
+            .filter_map(|url| index.blobs.get(index.sources.get(url)?)?.dhash)
+            .collect();
+        hashes.sort();
+        hashes.dedup();
+        hashes
+    }
+    // entries linking to an archived image within max_distance of any of hashes, the
+    // closest image per entry, closest first. exclude leaves out the entry being checked
+    pub fn similar(
+        &self,
+        hashes: &[u64],
+        max_distance: u32,
+        exclude: Option<&UniqueString>,
+    ) -> Vec<ImageMatch> {
+        let index = self.index.lock().unwrap();
+        let mut closest: HashMap<&String, u32> = HashMap::new();
+        for &hash in hashes {
+            // flat images (all one colour, blank screenshots) hash to 0 and match each
+            // other, they say nothing about who copied who
+            if hash == 0 {
+                continue;
+            }
+            for (distance, blob) in index.hashes.within(hash, max_distance) {
+                let best = closest.entry(blob).or_insert(distance);
+                *best = (*best).min(distance);
+            }
+        }
+        if closest.is_empty() {
+            return vec![];
+        }
+
+        let mut best: HashMap<&UniqueString, ImageMatch> = HashMap::new();
+        for (key, urls) in &index.entries {
+            if Some(key) == exclude {
+                continue;
+            }
+            for url in urls {
+                let Some(blob) = index.sources.get(url) else {
+                    continue;
+                };
+                let Some(&distance) = closest.get(blob) else {
+                    continue;
+                };
+                if best.get(key).is_none_or(|found| distance < found.distance) {
+                    best.insert(
+                        key,
+                        ImageMatch {
+                            key: key.clone(),
+                            blob: blob.clone(),
+                            source: url.clone(),
+                            distance,
+                        },
+                    );
+                }
+            }
+        }
+        let mut matches: Vec<ImageMatch> = best.into_values().collect();
+        matches.sort_by(|a, b| {
+            a.distance
+                .cmp(&b.distance)
+                .then_with(|| a.key.0.cmp(&b.key.0))
+        });
+        matches
+    }
+}
+
+// links come from scraped pages, so anyone can point the archiver at the network it runs
+// in. only public addresses are ever connected to: hostnames go through PublicOnly, ip
+// literals are checked here and on every redirect
+fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .dns_resolver(Arc::new(PublicOnly))
+        .redirect(redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() >= 10 {
+                attempt.error("too many redirects")
+            } else if let Err(e) = check_host(attempt.url()) {
+                attempt.error(e)
+            } else {
+                attempt.follow()
+            }
+        }))
+        .build()
+        .expect("cant build http client")
+}
+
+struct PublicOnly;
+
+impl Resolve for PublicOnly {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_owned();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{host} has no public address").into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn check_host(url: &reqwest::Url) -> Result<(), String> {
+    let host = url.host_str().ok_or("no host")?;
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) if !is_public(ip) => Err(format!("{ip} isnt a public address")),
+        _ => Ok(()),
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match embedded_v4(ip) {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+// v6 addresses that end up at a v4 one: mapped ::ffff:a.b.c.d, nat64 64:ff9b::a.b.c.d
+// and 6to4 2002:aabb:ccdd::
+fn embedded_v4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let s = ip.segments();
+    let from = |high: u16, low: u16| {
+        let [a, b] = high.to_be_bytes();
+        let [c, d] = low.to_be_bytes();
+        Ipv4Addr::new(a, b, c, d)
+    };
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        Some(ip)
+    } else if s[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        Some(from(s[6], s[7]))
+    } else if s[0] == 0x2002 {
+        Some(from(s[1], s[2]))
+    } else {
+        None
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // 0/8, carrier grade nat 100.64/10, benchmarking 198.18/15, reserved 240/4
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 198 && (18..20).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local fc00::/7, link local fe80::/10, local use nat64 64:ff9b:1::/48
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        || ip.segments()[..3] == [0x64, 0xff9b, 1])
+}
+
+async fn download(client: &reqwest::Client, url: &str) -> Result<Vec<u8>, String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    check_host(&parsed)?;
+    let mut response = client
+        .get(url)
+        .timeout(Duration::from_secs(60))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?;
+    if response
+        .content_length()
+        .is_some_and(|length| length as usize > MAX_MEDIA_SIZE)
+    {
+        return Err("too large".into());
+    }
+    // content length can be missing or lie
+    let mut bytes = vec![];
+    while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+        if bytes.len() + chunk.len() > MAX_MEDIA_SIZE {
+            return Err("too large".into());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    if bytes.is_empty() {
+        return Err("empty".into());
+    }
+    Ok(bytes)
+}
+
+// never returns, picks up new links every ARCHIVE_INTERVAL
+pub async fn archive_media(database: &Database) {
+    let mut interval = tokio::time::interval(ARCHIVE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let pass = database.media.archive(database).await;
+        if pass.fetched > 0 || pass.failed > 0 || pass.thumbnails > 0 {
+            println!(
+                "archived {} media files, {} failed, {} thumbnails made",
+                pass.fetched, pass.failed, pass.thumbnails
+            );
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{embedder::fnv1a, ranking::SearchOptions};
+
+// pages of /query results. offset is the simple way, the cursor remembers how many
+// entries existed on the first page and the last (score, id) handed out. ids only ever
+// get appended, so leaving out anything newer keeps rrf positions (and so scores) from
+// shifting under the reader, and the (score, id) covers entries edited in between
+
+pub const DEFAULT_LIMIT: usize = 50;
+pub const MAX_LIMIT: usize = 500;
+// deepest a page can end, everything above it gets ranked for every page so this is
+// what bounds the work (and memory) a single /query can ask for
+pub const MAX_DEPTH: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cursor {
+    // how many results came before, so the next search knows how deep to go
+    pub depth: usize,
+    // database length when the first page was made
+    pub snapshot: usize,
+    pub score: f32,
+    pub id: usize,
+    // query and options it was made for, a cursor from another search means nothing
+    pub fingerprint: u64,
+}
+
+impl Cursor {
+    // opaque to clients, just the fields as hex
+    pub fn encode(&self) -> String {
+        let mut bytes = Vec::with_capacity(36);
+        bytes.extend_from_slice(&(self.depth as u64).to_le_bytes());
+        bytes.extend_from_slice(&(self.snapshot as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.score.to_bits().to_le_bytes());
+        bytes.extend_from_slice(&(self.id as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.fingerprint.to_le_bytes());
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+    pub fn decode(text: &str) -> Option<Cursor> {
+        if text.len() != 72 || !text.is_ascii() {
+            return None;
+        }
+        let bytes: Vec<u8> = (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16))
+            .collect::<Result<_, _>>()
+            .ok()?;
+        let depth = u64::from_le_bytes(bytes[0..8].try_into().ok()?);
+        // never handed out, slice stops making cursors at MAX_DEPTH
+        if depth >= MAX_DEPTH as u64 {
+            return None;
+        }
+        Some(Cursor {
+            depth: depth as usize,
+            snapshot: u64::from_le_bytes(bytes[8..16].try_into().ok()?) as usize,
+            score: f32::from_bits(u32::from_le_bytes(bytes[16..20].try_into().ok()?)),
+            id: u64::from_le_bytes(bytes[20..28].try_into().ok()?) as usize,
+            fingerprint: u64::from_le_bytes(bytes[28..36].try_into().ok()?),
+        })
+    }
+    // results go best score first, ties by lower id, same as ranking::fuse
+    fn comes_after(&self, score: f32, id: usize) -> bool {
+        score < self.score || (score == self.score && id > self.id)
+    }
+}
+
+pub fn fingerprint(query: &str, options: &SearchOptions) -> u64 {
+    fnv1a(format!("{query}\0{options:?}").as_bytes())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Paging {
+    pub limit: usize,
+    // ignored when there is a cursor
+    pub offset: usize,
+    pub cursor: Option<Cursor>,
+}
+
+impl Default for Paging {
+    fn default() -> Self {
+        Paging::first(DEFAULT_LIMIT)
+    }
+}
+
+impl Paging {
+    pub fn first(limit: usize) -> Paging {
+        Paging {
+            limit,
+            offset: 0,
+            cursor: None,
+        }
+    }
+    pub fn depth(&self) -> usize {
+        self.cursor.map_or(self.offset, |cursor| cursor.depth)
+    }
+    // false when the page would end past MAX_DEPTH
+    pub fn in_bounds(&self) -> bool {
+        self.depth()
+            .checked_add(self.limit)
+            .is_some_and(|end| end <= MAX_DEPTH)
+    }
+    // one past the page, that extra result is how we know theres a next one
+    pub fn fetch_count(&self) -> usize {
+        self.depth() + self.limit + 1
+    }
+    // how many entries this page can see, current_length for a first page
+    pub fn snapshot(&self, current_length: usize) -> usize {
+        self.cursor
+            .map_or(current_length, |cursor| cursor.snapshot.min(current_length))
+    }
+    // ranked has to be the top fetch_count() results in order
+    pub fn slice(
+        &self,
+        ranked: Vec<(f32, usize)>,
+        snapshot: usize,
+        fingerprint: u64,
+    ) -> (Vec<(f32, usize)>, Option<Cursor>) {
+        let mut page: Vec<(f32, usize)> = match &self.cursor {
+            Some(cursor) => ranked
+                .into_iter()
+                .filter(|(score, id)| cursor.comes_after(*score, *id))
+                .take(self.limit + 1)
+                .collect(),
+            None => ranked
+                .into_iter()
+                .skip(self.offset)
+                .take(self.limit + 1)
+                .collect(),
+        };
+
+        let has_more = page.len() > self.limit;
+        page.truncate(self.limit);
+        let next = match page.last() {
+            Some(&(score, id)) if has_more && self.depth() + page.len() < MAX_DEPTH => {
+                Some(Cursor {
+                    depth: self.depth() + page.len(),
+                    snapshot,
+                    score,
+                    id,
+                    fingerprint,
+                })
+            }
+            _ => None,
+        };
+        (page, next)
+    }
+}
@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+
+use crate::embedder;
+
+// cheap copies of the embeddings for the first pass of a search, the full f32
+// vectors are only touched again to rescore whatever survives. the copies sit next to
+// the f32 ones (roughly 30% on top of the f32 ones) so theyre only kept with SOM_BACKEND_QUANTIZE=1
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantization {
+    // full precision only
+    #[default]
+    None,
+    // one byte per dimension, scaled so the largest component is 127
+    Int8,
+    // one bit per dimension (the sign), compared with hamming distance
+    Binary,
+}
+
+#[derive(Debug, Default)]
+pub struct QuantizedVectors {
+    dims: usize,
+    // false leaves everything below empty
+    keep: bool,
+    int8: Vec<i8>,
+    int8_norms: Vec<f32>,
+    binary: Vec<u64>,
+}
+
+pub struct QuantizedQuery {
+    int8: Vec<i8>,
+    int8_norm: f32,
+    binary: Vec<u64>,
+}
+
+fn words_for(dims: usize) -> usize {
+    dims.div_ceil(64)
+}
+
+fn to_int8(vector: &[f32]) -> (Vec<i8>, f32) {
+    let max_abs = vector.iter().fold(0.0f32, |acc, x| acc.max(x.abs()));
+    // per vector scale cancels out in cosine so it doesnt need storing
+    let scale = if max_abs > 0.0 { 127.0 / max_abs } else { 0.0 };
+    let quantized: Vec<i8> = vector
+        .iter()
+        .map(|x| (x * scale).round().clamp(-127.0, 127.0) as i8)
+        .collect();
+    let norm = quantized
+        .iter()
+        .map(|x| (*x as i32 * *x as i32) as f32)
+        .sum::<f32>()
+        .sqrt();
+    (quantized, norm)
+}
+
+fn to_binary(vector: &[f32]) -> Vec<u64> {
+    let mut words = vec![0u64; words_for(vector.len())];
+    for (i, x) in vector.iter().enumerate() {
+        if *x > 0.0 {
+            words[i / 64] |= 1 << (i % 64);
+        }
+    }
+    words
+}
+
+pub fn quantize_query(vector: &[f32]) -> QuantizedQuery {
+    let (int8, int8_norm) = to_int8(vector);
+    QuantizedQuery {
+        int8,
+        int8_norm,
+        binary: to_binary(vector),
+    }
+}
+
+impl QuantizedVectors {
+    pub fn new(dims: usize, keep: bool) -> QuantizedVectors {
+        QuantizedVectors {
+            dims,
+            keep,
+            ..Default::default()
+        }
+    }
+    pub fn from_env(dims: usize) -> QuantizedVectors {
+        QuantizedVectors::new(dims, embedder::env_number("SOM_BACKEND_QUANTIZE").is_some())
+    }
+    pub fn keeps(&self) -> bool {
+        self.keep
+    }
+    pub fn len(&self) -> usize {
+        self.int8_norms.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.int8_norms.is_empty()
+    }
+    // same order as processed
+    pub fn push(&mut self, vector: &[f32]) {
+        assert!(vector.len() == self.dims);
+        if !self.keep {
+            return;
+        }
+        let (int8, norm) = to_int8(vector);
+        self.int8.extend_from_slice(&int8);
+        self.int8_norms.push(norm);
+        self.binary.extend_from_slice(&to_binary(vector));
+    }
+    pub fn set(&mut self, index: usize, vector: &[f32]) {
+        assert!(vector.len() == self.dims);
+        if !self.keep {
+            return;
+        }
+        let (int8, norm) = to_int8(vector);
+        self.int8[index * self.dims..(index + 1) * self.dims].copy_from_slice(&int8);
+        self.int8_norms[index] = norm;
+        let words = words_for(self.dims);
+        self.binary[index * words..(index + 1) * words].copy_from_slice(&to_binary(vector));
+    }
+    // approximate cosine, same -1..1 range as comparare_cos. None when theres no such
+    // copy to compare against, the caller has to go to the full vectors
+    pub fn similarity(
+        &self,
+        kind: Quantization,
+        query: &QuantizedQuery,
+        index: usize,
+    ) -> Option<f32> {
+        if !self.keep {
+            return None;
+        }
+        Some(match kind {
+            Quantization::Int8 => {
+                let stored = &self.int8[index * self.dims..(index + 1) * self.dims];
+                let dot: i32 = stored
+                    .iter()
+                    .zip(&query.int8)
+                    .map(|(a, b)| *a as i32 * *b as i32)
+                    .sum();
+                let norms = self.int8_norms[index] * query.int8_norm;
+                if norms <= 0.0 {
+                    0.0
+                } else {
+                    dot as f32 / norms
+                }
+            }
+            Quantization::Binary => {
+                let words = words_for(self.dims);
+                let stored = &self.binary[index * words..(index + 1) * words];
+                let differing: u32 = stored
+                    .iter()
+                    .zip(&query.binary)
+                    .map(|(a, b)| (a ^ b).count_ones())
+                    .sum();
+                1.0 - 2.0 * differing as f32 / self.dims as f32
+            }
+            Quantization::None => return None,
+        })
+    }
+}
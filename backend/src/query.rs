@@ -0,0 +1,619 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::{
+    data::{DatabasePage, EVENTS, FilterFields, ScrapedMainPageEnum},
+    lexical::{self, InvertedIndex},
+};
+
+// the /query syntax, everything is space separated:
+//   words             ranked, not required
+//   "some phrase"     has to appear in that order, also ranked
+//   -word -"phrase"   excluded
+//   a OR b            either one, binds tighter than the implicit and between clauses
+//   field:value       filters, see Filter
+// filters run before ranking so a narrow filter still fills a page
+
+#[derive(Debug, PartialEq)]
+pub struct QueryError {
+    // byte offset into the query
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at character {})", self.message, self.position)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+fn error<T>(position: usize, message: impl Into<String>) -> Result<T, QueryError> {
+    Err(QueryError {
+        position,
+        message: message.into(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Comparison {
+    pub op: Op,
+    pub value: u64,
+}
+
+impl Comparison {
+    fn matches(&self, actual: u64) -> bool {
+        match self.op {
+            Op::Eq => actual == self.value,
+            Op::Gt => actual > self.value,
+            Op::Ge => actual >= self.value,
+            Op::Lt => actual < self.value,
+            Op::Le => actual <= self.value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HasField {
+    Repo,
+    Demo,
+    Readme,
+    Image,
+    Updates,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    // exact, case insensitive
+    Author(String),
+    // prefix of an EVENTS name, so event:summer works
+    Event(String),
+    Has(HasField),
+    Followers(Comparison),
+    Stonks(Comparison),
+    // seconds, values can have units like time:>2h30m
+    Time(Comparison),
+    Updates(Comparison),
+}
+
+const FIELDS: &[&str] = &[
+    "author",
+    "event",
+    "has",
+    "followers",
+    "stonks",
+    "time",
+    "updates",
+];
+
+impl Filter {
+    fn matches(&self, fields: &FilterFields) -> bool {
+        match self {
+            Filter::Author(author) => fields.author.to_lowercase() == *author,
+            Filter::Event(event) => fields.event.starts_with(event.as_str()),
+            Filter::Has(HasField::Repo) => fields.has_repo,
+            Filter::Has(HasField::Demo) => fields.has_demo,
+            Filter::Has(HasField::Readme) => fields.has_readme,
+            Filter::Has(HasField::Image) => fields.has_image,
+            Filter::Has(HasField::Updates) => fields.updates > 0,
+            Filter::Followers(cmp) => cmp.matches(fields.followers as u64),
+            // events without stonks never match, rather than counting as 0
+            Filter::Stonks(cmp) => fields.stonks.is_some_and(|s| cmp.matches(s as u64)),
+            Filter::Time(cmp) => cmp.matches(fields.time_seconds),
+            Filter::Updates(cmp) => cmp.matches(fields.updates as u64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Atom {
+    // quoted says whether it was written as a phrase, "a b" needs the order, a-b is just a word
+    Text {
+        raw: String,
+        tokens: Vec<String>,
+        quoted: bool,
+    },
+    Filter(Filter),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clause {
+    pub negated: bool,
+    pub atom: Atom,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ParsedQuery {
+    // and of ors
+    pub groups: Vec<Vec<Clause>>,
+}
+
+impl ParsedQuery {
+    // what goes to the embedder and bm25, only the positive text
+    pub fn ranking_text(&self) -> String {
+        let mut parts = vec![];
+        for clause in self.groups.iter().flatten() {
+            if let (false, Atom::Text { raw, .. }) = (clause.negated, &clause.atom) {
+                parts.push(raw.as_str());
+            }
+        }
+        parts.join(" ")
+    }
+    // a lone unquoted word only steers the ranking, anything else has to hold
+    fn is_constraint(group: &[Clause]) -> bool {
+        !matches!(
+            group,
+            [Clause {
+                negated: false,
+                atom: Atom::Text { quoted: false, .. },
+            }]
+        )
+    }
+    pub fn has_constraints(&self) -> bool {
+        self.groups.iter().any(|group| Self::is_constraint(group))
+    }
+    // one flag per doc, same order as raw_text
+    pub fn allowed(&self, index: &InvertedIndex, pages: &[ScrapedMainPageEnum]) -> Vec<bool> {
+        let constraints: Vec<&Vec<Clause>> = self
+            .groups
+            .iter()
+            .filter(|group| Self::is_constraint(group))
+            .collect();
+
+        // one postings walk per distinct token instead of tokenizing every page
+        let mut docs_with = HashMap::new();
+        for clause in constraints.iter().copied().flatten() {
+            if let Atom::Text { tokens, .. } = &clause.atom {
+                for token in tokens {
+                    docs_with
+                        .entry(token.as_str())
+                        .or_insert_with(|| index.documents_with(token).collect::<HashSet<usize>>());
+                }
+            }
+        }
+
+        pages
+            .iter()
+            .enumerate()
+            .map(|(doc, page)| {
+                let fields = page.filter_fields();
+                constraints.iter().all(|group| {
+                    group.iter().any(|clause| {
+                        let hit = match &clause.atom {
+                            Atom::Filter(filter) => filter.matches(&fields),
+                            Atom::Text { tokens, .. } => {
+                                tokens
+                                    .iter()
+                                    .all(|token| docs_with[token.as_str()].contains(&doc))
+                                    && (tokens.len() == 1 || contains_phrase(page, tokens))
+                            }
+                        };
+                        hit != clause.negated
+                    })
+                })
+            })
+            .collect()
+    }
+}
+
+// within a single field, the index only knows a doc has the words somewhere
+fn contains_phrase(page: &ScrapedMainPageEnum, phrase: &[String]) -> bool {
+    page.lexical_fields().iter().any(|(_, text)| {
+        let tokens: Vec<String> = lexical::tokenize(text).collect();
+        tokens.windows(phrase.len()).any(|window| window == phrase)
+    })
+}
+
+pub fn parse(query: &str) -> Result<ParsedQuery, QueryError> {
+    let mut parsed = ParsedQuery::default();
+    let mut pending_or: Option<usize> = None;
+    let mut pos = 0;
+
+    loop {
+        pos = skip_whitespace(query, pos);
+        if pos >= query.len() {
+            break;
+        }
+        let start = pos;
+
+        let (word, end) = read_word(query, pos)?;
+        if word == "OR" {
+            if parsed.groups.is_empty() || pending_or.is_some() {
+                return error(start, "OR needs something on both sides");
+            }
+            pending_or = Some(start);
+            pos = end;
+            continue;
+        }
+
+        let (clause, end) = read_clause(query, pos)?;
+        pos = end;
+        let Some(clause) = clause else {
+            continue;
+        };
+        match pending_or.take() {
+            Some(_) => parsed.groups.last_mut().unwrap().push(clause),
+            None => parsed.groups.push(vec![clause]),
+        }
+    }
+
+    if let Some(or_at) = pending_or {
+        return error(or_at, "OR needs something on both sides");
+    }
+    Ok(parsed)
+}
+
+fn skip_whitespace(query: &str, pos: usize) -> usize {
+    query[pos..]
+        .find(|c: char| !c.is_whitespace())
+        .map_or(query.len(), |offset| pos + offset)
+}
+
+// up to the next whitespace, quotes keep their spaces
+fn read_word(query: &str, pos: usize) -> Result<(&str, usize), QueryError> {
+    let mut in_quote = None;
+    for (offset, c) in query[pos..].char_indices() {
+        if c == '"' {
+            in_quote = match in_quote {
+                None => Some(pos + offset),
+                Some(_) => None,
+            };
+        } else if c.is_whitespace() && in_quote.is_none() {
+            return Ok((&query[pos..pos + offset], pos + offset));
+        }
+    }
+    match in_quote {
+        Some(quote_at) => error(quote_at, "unclosed quote"),
+        None => Ok((&query[pos..], query.len())),
+    }
+}
+
+// None for words that tokenize to nothing, like a lone "++"
+fn read_clause(query: &str, pos: usize) -> Result<(Option<Clause>, usize), QueryError> {
+    let (word, end) = read_word(query, pos)?;
+    let (negated, word, word_at) = match word.strip_prefix('-') {
+        Some("") => return error(pos, "nothing after -"),
+        Some(rest) => (true, rest, pos + 1),
+        None => (false, word, pos),
+    };
+
+    if let Some(inner) = word.strip_prefix('"') {
+        let Some(inner) = inner.strip_suffix('"') else {
+            return error(word_at, "text after a closing quote needs a space");
+        };
+        let tokens: Vec<String> = lexical::tokenize(inner).collect();
+        if tokens.is_empty() {
+            return error(word_at, "empty phrase");
+        }
+        let atom = Atom::Text {
+            raw: inner.to_string(),
+            tokens,
+            quoted: true,
+        };
+        return Ok((Some(Clause { negated, atom }), end));
+    }
+
+    if let Some((field, value)) = word.split_once(':') {
+        let field = field.to_lowercase();
+        if FIELDS.contains(&field.as_str()) {
+            let value_at = word_at + field.len() + 1;
+            let value = value.trim_matches('"');
+            let filter = parse_filter(&field, value, value_at)?;
+            let atom = Atom::Filter(filter);
+            return Ok((Some(Clause { negated, atom }), end));
+        }
+        // urls and the like are fine as text, a typo of a field isnt
+        if !value.starts_with("//") && !field.is_empty() && field.chars().all(char::is_alphabetic) {
+            return error(
+                word_at,
+                format!(
+                    "unknown filter {field}:, try one of {}",
+                    FIELDS
+                        .iter()
+                        .map(|f| format!("{f}:"))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                ),
+            );
+        }
+    }
+
+    let tokens: Vec<String> = lexical::tokenize(word).collect();
+    if tokens.is_empty() {
+        return Ok((None, end));
+    }
+    let atom = Atom::Text {
+        raw: word.to_string(),
+        tokens,
+        quoted: false,
+    };
+    Ok((Some(Clause { negated, atom }), end))
+}
+
+fn parse_filter(field: &str, value: &str, value_at: usize) -> Result<Filter, QueryError> {
+    if value.is_empty() {
+        return error(value_at, format!("{field}: needs a value"));
+    }
+    match field {
+        "author" => Ok(Filter::Author(value.trim_start_matches('@').to_lowercase())),
+        "event" => {
+            let event: String = value
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect();
+            if !EVENTS.iter().any(|known| known.starts_with(&event)) || event.is_empty() {
+                return error(
+                    value_at,
+                    format!("unknown event {value}, try one of {}", EVENTS.join(" ")),
+                );
+            }
+            Ok(Filter::Event(event))
+        }
+        "has" => match value.to_lowercase().as_str() {
+            "repo" => Ok(Filter::Has(HasField::Repo)),
+            "demo" => Ok(Filter::Has(HasField::Demo)),
+            "readme" => Ok(Filter::Has(HasField::Readme)),
+            "image" => Ok(Filter::Has(HasField::Image)),
+            "updates" => Ok(Filter::Has(HasField::Updates)),
+            _ => error(
+                value_at,
+                format!("has:{value} isnt a thing, try repo, demo, readme, image or updates"),
+            ),
+        },
+        "followers" => Ok(Filter::Followers(parse_comparison(
+            field, value, value_at, false,
+        )?)),
+        "stonks" => Ok(Filter::Stonks(parse_comparison(
+            field, value, value_at, false,
+        )?)),
+        "time" => Ok(Filter::Time(parse_comparison(
+            field, value, value_at, true,
+        )?)),
+        "updates" => Ok(Filter::Updates(parse_comparison(
+            field, value, value_at, false,
+        )?)),
+        _ => unreachable!("every name in FIELDS has a parser"),
+    }
+}
+
+fn parse_comparison(
+    field: &str,
+    value: &str,
+    value_at: usize,
+    duration: bool,
+) -> Result<Comparison, QueryError> {
+    let (op, number) = if let Some(rest) = value.strip_prefix(">=") {
+        (Op::Ge, rest)
+    } else if let Some(rest) = value.strip_prefix("<=") {
+        (Op::Le, rest)
+    } else if let Some(rest) = value.strip_prefix('>') {
+        (Op::Gt, rest)
+    } else if let Some(rest) = value.strip_prefix('<') {
+        (Op::Lt, rest)
+    } else if let Some(rest) = value.strip_prefix('=') {
+        (Op::Eq, rest)
+    } else {
+        (Op::Eq, value)
+    };
+
+    let parsed = if duration {
+        parse_duration(number)
+    } else {
+        number.parse().ok()
+    };
+    match parsed {
+        Some(value) => Ok(Comparison { op, value }),
+        None if duration => error(
+            value_at,
+            format!("{field}: wants seconds or a duration like >3600 or >=1h30m"),
+        ),
+        None => error(value_at, format!("{field}: wants a number like >20")),
+    }
+}
+
+// "90", "2h", "1h30m", "3 hours 5 mins", bare numbers are seconds
+pub fn parse_duration(text: &str) -> Option<u64> {
+    let mut total = 0u64;
+    let mut seen_number = false;
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return None;
+        }
+        let number: u64 = rest[..digits].parse().ok()?;
+        rest = rest[digits..].trim_start();
+
+        let unit_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let multiplier = match rest[..unit_len].to_lowercase().as_str() {
+            "" | "s" | "sec" | "secs" | "second" | "seconds" => 1,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600,
+            "d" | "day" | "days" => 86400,
+            _ => return None,
+        };
+        total = total.checked_add(number.checked_mul(multiplier)?)?;
+        seen_number = true;
+        // journey writes things like "3 hours, 5 minutes"
+        rest = rest[unit_len..].trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    }
+    seen_number.then_some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(raw: &str, quoted: bool) -> Atom {
+        Atom::Text {
+            raw: raw.into(),
+            tokens: lexical::tokenize(raw).collect(),
+            quoted,
+        }
+    }
+
+    fn clause(negated: bool, atom: Atom) -> Clause {
+        Clause { negated, atom }
+    }
+
+    fn error_at(query: &str) -> usize {
+        parse(query).unwrap_err().position
+    }
+
+    #[test]
+    fn plain_words_only_rank() {
+        let parsed = parse("  rust   discord-bot ").unwrap();
+        assert_eq!(
+            parsed.groups,
+            vec![
+                vec![clause(false, text("rust", false))],
+                vec![clause(false, text("discord-bot", false))],
+            ]
+        );
+        assert!(!parsed.has_constraints());
+        assert_eq!(parsed.ranking_text(), "rust discord-bot");
+        assert_eq!(parse("++ rust").unwrap().groups.len(), 1);
+    }
+
+    #[test]
+    fn quotes() {
+        let parsed = parse(r#"game "made in godot""#).unwrap();
+        assert_eq!(
+            parsed.groups[1],
+            vec![clause(false, text("made in godot", true))]
+        );
+        assert!(parsed.has_constraints());
+        assert_eq!(parsed.ranking_text(), "game made in godot");
+
+        assert_eq!(error_at(r#"game "made in"#), 5);
+        assert_eq!(error_at(r#""made"in"#), 0);
+        assert_eq!(error_at(r#"a "!!""#), 2);
+    }
+
+    #[test]
+    fn negation() {
+        let parsed = parse(r#"bot -discord -"web app""#).unwrap();
+        assert_eq!(
+            parsed.groups,
+            vec![
+                vec![clause(false, text("bot", false))],
+                vec![clause(true, text("discord", false))],
+                vec![clause(true, text("web app", true))],
+            ]
+        );
+        assert!(parsed.has_constraints());
+        // excluded words dont steer the ranking
+        assert_eq!(parsed.ranking_text(), "bot");
+
+        assert_eq!(error_at("bot - discord"), 4);
+    }
+
+    #[test]
+    fn or_groups() {
+        let parsed = parse("rust OR go OR zig cli").unwrap();
+        assert_eq!(
+            parsed.groups,
+            vec![
+                vec![
+                    clause(false, text("rust", false)),
+                    clause(false, text("go", false)),
+                    clause(false, text("zig", false)),
+                ],
+                vec![clause(false, text("cli", false))],
+            ]
+        );
+        assert!(parsed.has_constraints());
+        // lowercase is just a word
+        assert_eq!(parse("rust or go").unwrap().groups.len(), 3);
+
+        assert_eq!(error_at("OR rust"), 0);
+        assert_eq!(error_at("rust OR"), 5);
+        assert_eq!(error_at("rust OR OR go"), 8);
+    }
+
+    #[test]
+    fn filters() {
+        let parsed = parse(
+            "author:@SomeOne event:summer has:Repo followers:>=20 stonks:<5 time:>1h30m updates:3",
+        )
+        .unwrap();
+        let filters: Vec<Filter> = parsed
+            .groups
+            .iter()
+            .flatten()
+            .map(|clause| match &clause.atom {
+                Atom::Filter(filter) => filter.clone(),
+                atom => panic!("not a filter: {atom:?}"),
+            })
+            .collect();
+        let cmp = |op, value| Comparison { op, value };
+        assert_eq!(
+            filters,
+            vec![
+                Filter::Author("someone".into()),
+                Filter::Event("summer".into()),
+                Filter::Has(HasField::Repo),
+                Filter::Followers(cmp(Op::Ge, 20)),
+                Filter::Stonks(cmp(Op::Lt, 5)),
+                Filter::Time(cmp(Op::Gt, 5400)),
+                Filter::Updates(cmp(Op::Eq, 3)),
+            ]
+        );
+        assert_eq!(parsed.ranking_text(), "");
+
+        let parsed = parse("-has:demo").unwrap();
+        assert_eq!(
+            parsed.groups,
+            vec![vec![clause(
+                true,
+                Atom::Filter(Filter::Has(HasField::Demo))
+            )]]
+        );
+        // links arent filters
+        assert_eq!(
+            parse("https://github.com/x").unwrap().groups[0],
+            vec![clause(false, text("https://github.com/x", false))]
+        );
+    }
+
+    #[test]
+    fn bad_filters() {
+        assert_eq!(error_at("rust colour:red"), 5);
+        assert_eq!(error_at("author:"), 7);
+        assert_eq!(error_at("event:winter"), 6);
+        assert_eq!(error_at("has:cake"), 4);
+        assert_eq!(error_at("followers:lots"), 10);
+        assert_eq!(error_at("followers:>"), 10);
+        assert_eq!(error_at("time:>soon"), 5);
+        assert!(parse("colour:red").unwrap_err().message.contains("author:"));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Some(90));
+        assert_eq!(parse_duration("2h"), Some(7200));
+        assert_eq!(parse_duration("1h30m"), Some(5400));
+        assert_eq!(parse_duration("3 hours, 5 minutes"), Some(11100));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("5 fortnights"), None);
+        assert_eq!(parse_duration("99999999999999999999"), None);
+    }
+}
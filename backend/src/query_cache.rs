@@ -0,0 +1,113 @@
+use lru::LruCache;
+use serde::Serialize;
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::embedder::{self, EmbedError, Embedder};
+
+// query text -> embedding in front of the embedder for /query. popular searches and
+// the blank homepage query come in all day and every miss is a round trip to the model.
+// entries also expire so a cache that never fills up doesnt hold on to them forever
+
+pub const DEFAULT_CAPACITY: usize = 4096;
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+// the cache key and also what gets embedded, so "rust  cli " and "rust cli" share an
+// entry. case is left alone, it can mean something to the model ("Go" the language,
+// "go" the verb)
+pub fn normalize(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[derive(Serialize, Debug)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub ttl_seconds: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+pub struct QueryCache {
+    entries: Mutex<LruCache<String, (Instant, Vec<f32>)>>,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Default for QueryCache {
+    fn default() -> Self {
+        QueryCache::new(DEFAULT_CAPACITY, DEFAULT_TTL)
+    }
+}
+
+impl QueryCache {
+    pub fn new(capacity: usize, ttl: Duration) -> QueryCache {
+        QueryCache {
+            entries: Mutex::new(LruCache::new(
+                NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN),
+            )),
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+    //   SOM_BACKEND_QUERY_CACHE      how many queries to keep
+    //   SOM_BACKEND_QUERY_CACHE_TTL  seconds before one has to be embedded again
+    pub fn from_env() -> QueryCache {
+        QueryCache::new(
+            embedder::env_number("SOM_BACKEND_QUERY_CACHE").unwrap_or(DEFAULT_CAPACITY),
+            embedder::env_number("SOM_BACKEND_QUERY_CACHE_TTL")
+                .map_or(DEFAULT_TTL, |seconds| Duration::from_secs(seconds as u64)),
+        )
+    }
+    // errors arent cached, the next search tries the embedder again
+    pub async fn embed(
+        &self,
+        embedder: &dyn Embedder,
+        query: &str,
+    ) -> Result<Vec<f32>, EmbedError> {
+        let key = normalize(query);
+        if let Some(embed) = self.get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(embed);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        // not holding the lock across the await, two misses on one query both embed it
+        let embed = embedder.embed(&key).await?;
+        self.entries
+            .lock()
+            .unwrap()
+            .put(key, (Instant::now(), embed.clone()));
+        Ok(embed)
+    }
+    fn get(&self, key: &str) -> Option<Vec<f32>> {
+        let mut entries = self.entries.lock().unwrap();
+        let (stored_at, embed) = entries.get(key)?;
+        if stored_at.elapsed() > self.ttl {
+            entries.pop(key);
+            return None;
+        }
+        Some(embed.clone())
+    }
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.entries.lock().unwrap();
+        CacheStats {
+            entries: entries.len(),
+            capacity: entries.cap().get(),
+            ttl_seconds: self.ttl.as_secs(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
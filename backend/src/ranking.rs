@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::quantize::Quantization;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Fusion {
+    // reciprocal rank fusion, only cares about positions in each list
+    Rrf,
+    // scale both scores by their best hit then blend
+    Weighted,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SearchOptions {
+    pub fusion: Fusion,
+    // 0.0 is keywords only, 1.0 is embeddings only
+    pub semantic_weight: f32,
+    pub rrf_k: f32,
+    // hnsw beam width, higher is better recall but slower
+    pub ef_search: usize,
+    // which copy of the embeddings the first pass scans
+    pub quantization: Quantization,
+    // how many times k the first pass keeps for full precision rescoring
+    pub rescore_factor: usize,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            fusion: Fusion::Rrf,
+            semantic_weight: 0.5,
+            // the usual constant from the rrf paper
+            rrf_k: 60.0,
+            ef_search: 200,
+            quantization: Quantization::None,
+            rescore_factor: 4,
+        }
+    }
+}
+
+impl SearchOptions {
+    pub fn wants_semantic(&self) -> bool {
+        self.semantic_weight > 0.0
+    }
+    pub fn wants_lexical(&self) -> bool {
+        self.semantic_weight < 1.0
+    }
+    // how deep into each list to look before fusing, the tail of one list can still win
+    pub fn candidate_count(&self, k: usize) -> usize {
+        (k * 4).max(100)
+    }
+}
+
+// both inputs are (score, doc) sorted best first, output is the same shape.
+// multiplier is applied before the cut so a boosted doc from deep in a list can still make it
+pub fn fuse(
+    semantic: &[(f32, usize)],
+    lexical: &[(f32, usize)],
+    k: usize,
+    options: &SearchOptions,
+    multiplier: impl Fn(usize) -> f32,
+) -> Vec<(f32, usize)> {
+    let w = options.semantic_weight.clamp(0.0, 1.0);
+    let mut combined: HashMap<usize, f32> = HashMap::new();
+
+    match options.fusion {
+        Fusion::Rrf => {
+            for (position, (_, doc)) in semantic.iter().enumerate() {
+                *combined.entry(*doc).or_default() += w / (options.rrf_k + position as f32 + 1.0);
+            }
+            for (position, (_, doc)) in lexical.iter().enumerate() {
+                *combined.entry(*doc).or_default() +=
+                    (1.0 - w) / (options.rrf_k + position as f32 + 1.0);
+            }
+        }
+        Fusion::Weighted => {
+            for (score, doc) in normalize(semantic) {
+                *combined.entry(doc).or_default() += w * score;
+            }
+            for (score, doc) in normalize(lexical) {
+                *combined.entry(doc).or_default() += (1.0 - w) * score;
+            }
+        }
+    }
+
+    let mut fused: Vec<(f32, usize)> = combined
+        .into_iter()
+        .map(|(doc, score)| (score * multiplier(doc), doc))
+        .collect();
+    fused.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    fused.truncate(k);
+    fused
+}
+
+// relative to the best hit. the bottom of a list moves with how deep the page asked for
+// but the top doesnt, so a doc keeps its score from one page to the next
+fn normalize(ranked: &[(f32, usize)]) -> impl Iterator<Item = (f32, usize)> + '_ {
+    let top = ranked.iter().map(|x| x.0).fold(f32::NEG_INFINITY, f32::max);
+    ranked.iter().map(move |(score, doc)| {
+        if top > f32::EPSILON {
+            (score / top, *doc)
+        } else {
+            // only negative cosines, from a blank query. shifted so the best one is 1
+            (score - top + 1.0, *doc)
+        }
+    })
+}
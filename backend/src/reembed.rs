@@ -0,0 +1,160 @@
+use serde::Serialize;
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use crate::{database::Database, embedder::EmbedError, history};
+
+// swaps vectors from an old embedding model for ones from the current embedder. entries
+// are done a batch at a time and replaced in place, so until an entry's turn comes its
+// old vector keeps serving (lexical matching carries those, the similarity across two
+// models is noise). progress is shown on /admin/reembed.
+// retry_pending goes through the same path for entries stored while the embedder was down
+
+pub const BATCH_SIZE: usize = 128;
+// how often entries stored while the embedder was down get another go
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum Reembedded {
+    Done,
+    // the entry changed while its batch was at the embedder, /add already took care of it
+    Skipped,
+    // keeps its old vector, the next run picks it up again
+    Failed(EmbedError),
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ReembedProgress {
+    pub running: bool,
+    // what everything is being moved to
+    pub model: String,
+    pub total: usize,
+    pub done: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReembedStatus {
+    #[serde(flatten)]
+    pub progress: ReembedProgress,
+    // entries per model right now, the current one included
+    pub models: BTreeMap<String, usize>,
+    pub stale: usize,
+    // waiting on the retry worker, see retry_pending
+    pub pending: usize,
+}
+
+#[derive(Default)]
+pub struct ReembedJob {
+    progress: Mutex<ReembedProgress>,
+}
+
+impl ReembedJob {
+    pub fn progress(&self) -> ReembedProgress {
+        self.progress.lock().unwrap().clone()
+    }
+    pub fn status(&self, database: &Database) -> ReembedStatus {
+        ReembedStatus {
+            progress: self.progress(),
+            models: database.embedding_models(),
+            stale: database.stale_embeddings().len(),
+            pending: database.pending_embeddings().len(),
+        }
+    }
+    // false if a run is already going, only one at a time
+    fn begin(&self, model: &str, total: usize) -> bool {
+        let mut progress = self.progress.lock().unwrap();
+        if progress.running {
+            return false;
+        }
+        *progress = ReembedProgress {
+            running: true,
+            model: model.to_owned(),
+            total,
+            started_at: history::now(),
+            ..Default::default()
+        };
+        true
+    }
+    // returns once every stale entry has been tried, false if nothing was started
+    pub async fn run(&self, database: &Database) -> bool {
+        let stale = database.stale_embeddings();
+        if stale.is_empty() || !self.begin(database.embedder.model(), stale.len()) {
+            return false;
+        }
+        println!(
+            "re-embedding {} entries with {}",
+            stale.len(),
+            database.embedder.model()
+        );
+
+        for batch in stale.chunks(BATCH_SIZE) {
+            let results = database.reembed(batch).await;
+            let mut progress = self.progress.lock().unwrap();
+            let mut failed = 0;
+            for result in results {
+                match result {
+                    Reembedded::Done => progress.done += 1,
+                    Reembedded::Skipped => progress.skipped += 1,
+                    Reembedded::Failed(e) => {
+                        failed += 1;
+                        progress.last_error = Some(e.to_string());
+                    }
+                }
+            }
+            progress.failed += failed;
+            // the embedder already retried each request, a whole batch failing means its down
+            if failed == batch.len() {
+                eprintln!("stopping re-embed, whole batch failed");
+                break;
+            }
+        }
+
+        let mut progress = self.progress.lock().unwrap();
+        progress.running = false;
+        progress.finished_at = Some(history::now());
+        println!(
+            "re-embed finished: {} done, {} skipped, {} failed",
+            progress.done, progress.skipped, progress.failed
+        );
+        true
+    }
+}
+
+// never returns. add_entries stores entries without a new vector when the embedder is
+// down, this fills them in once it answers again
+pub async fn retry_pending(database: &Database) {
+    let mut interval = tokio::time::interval(RETRY_INTERVAL);
+    loop {
+        interval.tick().await;
+        let pending = database.pending_embeddings();
+        if pending.is_empty() {
+            continue;
+        }
+
+        let mut filled = 0;
+        for batch in pending.chunks(BATCH_SIZE) {
+            let results = database.reembed(batch).await;
+            filled += results
+                .iter()
+                .filter(|result| matches!(result, Reembedded::Done))
+                .count();
+            if results
+                .iter()
+                .all(|result| matches!(result, Reembedded::Failed(_)))
+            {
+                eprintln!(
+                    "embedder still down, {} entries waiting",
+                    pending.len() - filled
+                );
+                break;
+            }
+        }
+        if filled > 0 {
+            println!("embedded {filled} pending entries");
+        }
+    }
+}
@@ -0,0 +1,448 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    data::{ComputedData, DatabasePage, ScrapedMainPageEnum, UniqueString},
+    database::UnderlyingData,
+    embedder,
+    history::Revision,
+};
+
+// binary layout, everything little endian:
+//   magic (8 bytes) | version u32 | record count u64
+//   per record:
+//     page length u32 | page as compact json | (v6+) scraped_at u64
+//     has computed u8
+//     if computed: ai_description f32 | ai_code f32 | (v3+) score_multiplier f32
+//                  | (v4+) model length u32 | model | (v5+) outdated u8
+//                  | embedding [f32; 768]
+//   (v2+) history chain count u64
+//   per chain:
+//     key length u32 | key | revision count u32
+//     per revision: scraped_at u64 | page length u32 | page as compact json
+// before v6 each chain ended with a copy of the current page, see split_current
+// pages stay json because the event structs change shape often, the
+// embeddings are the bulk of the file and those are raw floats
+
+pub const MAGIC: &[u8; 8] = b"SRXNGDB\0";
+pub const VERSION: u32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageFormat {
+    Json,
+    Binary,
+}
+
+impl StorageFormat {
+    // for writing, anything not called .json gets the binary format
+    pub fn from_path(path: &str) -> StorageFormat {
+        if path.ends_with(".json") {
+            StorageFormat::Json
+        } else {
+            StorageFormat::Binary
+        }
+    }
+    // for reading, trust the contents over the name
+    pub fn detect(bytes: &[u8]) -> StorageFormat {
+        if bytes.starts_with(MAGIC) {
+            StorageFormat::Binary
+        } else {
+            StorageFormat::Json
+        }
+    }
+}
+
+// write next to the target then rename over it, a crash at any point leaves
+// either the old file or the new one on disk, never half of each
+pub fn write_atomic(path: &str, bytes: &[u8]) -> io::Result<()> {
+    let tmp = format!("{path}.tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, path)?;
+
+    // the rename only counts once the directory entry is flushed too
+    let parent = Path::new(path)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    File::open(parent)?.sync_all()
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+pub fn encode(data: &UnderlyingData, format: StorageFormat) -> io::Result<Vec<u8>> {
+    match format {
+        StorageFormat::Json => serde_json::to_vec_pretty(data).map_err(io::Error::other),
+        StorageFormat::Binary => {
+            let mut out = Vec::with_capacity(data.length * 4 * 1024);
+            write_binary(data, &mut out)?;
+            Ok(out)
+        }
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> io::Result<UnderlyingData> {
+    match StorageFormat::detect(bytes) {
+        StorageFormat::Json => {
+            let mut data: UnderlyingData =
+                serde_json::from_slice(bytes).map_err(|e| invalid(e.to_string()))?;
+            // json files from before scraped_at was its own field
+            if data.scraped_at.len() != data.length {
+                split_current(&mut data);
+            }
+            Ok(data)
+        }
+        StorageFormat::Binary => read_binary(&mut &bytes[..]),
+    }
+}
+
+pub fn write_binary(data: &UnderlyingData, out: &mut impl Write) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&(data.length as u64).to_le_bytes())?;
+
+    for ((page, computed), scraped_at) in data
+        .raw_text
+        .iter()
+        .zip(&data.processed)
+        .zip(&data.scraped_at)
+    {
+        let page_json = serde_json::to_vec(page).map_err(io::Error::other)?;
+        write_bytes(out, &page_json)?;
+        out.write_all(&scraped_at.to_le_bytes())?;
+
+        match computed {
+            Some(computed) => {
+                out.write_all(&[1])?;
+                out.write_all(&computed.ai_description.to_le_bytes())?;
+                out.write_all(&computed.ai_code.to_le_bytes())?;
+                out.write_all(&computed.score_multiplier.to_le_bytes())?;
+                write_bytes(out, computed.model.as_bytes())?;
+                out.write_all(&[computed.outdated as u8])?;
+                for value in computed.embedding {
+                    out.write_all(&value.to_le_bytes())?;
+                }
+            }
+            None => out.write_all(&[0])?,
+        }
+    }
+
+    out.write_all(&(data.history.len() as u64).to_le_bytes())?;
+    for (key, revisions) in &data.history {
+        write_bytes(out, key.0.as_bytes())?;
+        out.write_all(&(revisions.len() as u32).to_le_bytes())?;
+        for revision in revisions {
+            out.write_all(&revision.scraped_at.to_le_bytes())?;
+            let page_json = serde_json::to_vec(&revision.page).map_err(io::Error::other)?;
+            write_bytes(out, &page_json)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_binary(input: &mut impl Read) -> io::Result<UnderlyingData> {
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a searxing database"));
+    }
+    let version = read_u32(input)?;
+    if version > VERSION {
+        return Err(invalid(format!(
+            "database version {version} is newer than this build"
+        )));
+    }
+    let count = read_u64(input)? as usize;
+
+    // dont trust the header for a huge allocation
+    let mut raw_text = Vec::with_capacity(count.min(1 << 16));
+    let mut processed = Vec::with_capacity(count.min(1 << 16));
+    let mut scraped_at = Vec::with_capacity(count.min(1 << 16));
+    for _ in 0..count {
+        let page = read_page(input)?;
+        scraped_at.push(if version >= 6 { read_u64(input)? } else { 0 });
+
+        let computed = match read_u8(input)? {
+            0 => None,
+            1 => {
+                let ai_description = read_f32(input)?;
+                let ai_code = read_f32(input)?;
+                let score_multiplier = if version >= 3 { read_f32(input)? } else { 1.0 };
+                let model = if version >= 4 {
+                    String::from_utf8(read_bytes(input)?).map_err(|e| invalid(e.to_string()))?
+                } else {
+                    embedder::LEGACY_MODEL.to_owned()
+                };
+                let outdated = version >= 5 && read_u8(input)? != 0;
+                let mut embedding = [0.0; 768];
+                for value in embedding.iter_mut() {
+                    *value = read_f32(input)?;
+                }
+                Some(ComputedData {
+                    embedding,
+                    ai_description,
+                    ai_code,
+                    score_multiplier,
+                    model,
+                    outdated,
+                })
+            }
+            flag => return Err(invalid(format!("bad computed flag {flag}"))),
+        };
+
+        raw_text.push(page);
+        processed.push(computed);
+    }
+
+    let mut history = HashMap::new();
+    if version >= 2 {
+        let chains = read_u64(input)?;
+        for _ in 0..chains {
+            let key = String::from_utf8(read_bytes(input)?).map_err(|e| invalid(e.to_string()))?;
+            let revision_count = read_u32(input)?;
+            let mut revisions = Vec::with_capacity(revision_count.min(1024) as usize);
+            for _ in 0..revision_count {
+                let scraped_at = read_u64(input)?;
+                let page = read_page(input)?;
+                revisions.push(Revision { scraped_at, page });
+            }
+            history.insert(UniqueString(key), revisions);
+        }
+    }
+
+    let mut data = UnderlyingData {
+        raw_text,
+        processed,
+        length: count,
+        history,
+        scraped_at,
+        ..Default::default()
+    };
+    if version < 6 {
+        split_current(&mut data);
+    }
+    Ok(data)
+}
+
+// older files kept the current page as the last revision of its chain too, move its
+// scrape time over and drop the copy. entries without a chain count as never scraped
+fn split_current(data: &mut UnderlyingData) {
+    data.scraped_at = vec![0; data.length];
+    for (page, scraped_at) in data.raw_text.iter().zip(&mut data.scraped_at) {
+        let key = page.unique_string();
+        let Some(chain) = data.history.get_mut(&key) else {
+            continue;
+        };
+        if chain.last().is_some_and(|last| last.page == *page) {
+            *scraped_at = chain.pop().unwrap().scraped_at;
+        }
+        if chain.is_empty() {
+            data.history.remove(&key);
+        }
+    }
+}
+
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    out.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    out.write_all(bytes)
+}
+fn read_bytes(input: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_u32(input)? as usize;
+    let mut bytes = vec![0u8; len];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+fn read_page(input: &mut impl Read) -> io::Result<ScrapedMainPageEnum> {
+    serde_json::from_slice(&read_bytes(input)?).map_err(|e| invalid(e.to_string()))
+}
+
+fn read_u8(input: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    input.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+fn read_f32(input: &mut impl Read) -> io::Result<f32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(f32::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Summer2025MainPage;
+
+    fn page(url: &str, description: &str) -> ScrapedMainPageEnum {
+        ScrapedMainPageEnum::Summer2025(Summer2025MainPage {
+            url: url.into(),
+            name: url.into(),
+            description: description.into(),
+            ..Default::default()
+        })
+    }
+
+    fn sample() -> UnderlyingData {
+        let mut embedding = [0.0; 768];
+        for (i, value) in embedding.iter_mut().enumerate() {
+            *value = i as f32 / 768.0 - 0.5;
+        }
+        let computed = ComputedData {
+            embedding,
+            ai_description: 0.25,
+            ai_code: 0.75,
+            score_multiplier: 2.0,
+            model: "some-model".into(),
+            outdated: true,
+        };
+        let mut history = HashMap::new();
+        history.insert(
+            UniqueString("a".into()),
+            vec![Revision {
+                scraped_at: 10,
+                page: page("a", "first"),
+            }],
+        );
+        UnderlyingData {
+            raw_text: vec![page("a", "second"), page("b", "no embedding yet")],
+            processed: vec![Some(computed), None],
+            length: 2,
+            history,
+            scraped_at: vec![20, 30],
+            ..Default::default()
+        }
+    }
+
+    fn assert_same(a: &UnderlyingData, b: &UnderlyingData) {
+        assert_eq!(a.length, b.length);
+        assert_eq!(a.raw_text, b.raw_text);
+        assert_eq!(a.processed.len(), b.processed.len());
+        for (a, b) in a.processed.iter().zip(&b.processed) {
+            match (a, b) {
+                (Some(a), Some(b)) => {
+                    assert_eq!(a.embedding, b.embedding);
+                    assert_eq!(a.ai_description, b.ai_description);
+                    assert_eq!(a.ai_code, b.ai_code);
+                    assert_eq!(a.score_multiplier, b.score_multiplier);
+                    assert_eq!(a.model, b.model);
+                    assert_eq!(a.outdated, b.outdated);
+                }
+                (None, None) => {}
+                _ => panic!("computed data went missing"),
+            }
+        }
+        assert_eq!(a.scraped_at, b.scraped_at);
+        assert_eq!(a.history.len(), b.history.len());
+        for (key, revisions) in &a.history {
+            let other = &b.history[key];
+            assert_eq!(revisions.len(), other.len());
+            for (a, b) in revisions.iter().zip(other) {
+                assert_eq!(a.scraped_at, b.scraped_at);
+                assert_eq!(a.page, b.page);
+            }
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let data = sample();
+        let bytes = encode(&data, StorageFormat::Binary).unwrap();
+        assert!(bytes.starts_with(MAGIC));
+        assert_same(&data, &decode(&bytes).unwrap());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let data = sample();
+        let bytes = encode(&data, StorageFormat::Json).unwrap();
+        assert_eq!(StorageFormat::detect(&bytes), StorageFormat::Json);
+        assert_same(&data, &decode(&bytes).unwrap());
+    }
+
+    // what the first binary release wrote: no multiplier, model, outdated flag or history
+    #[test]
+    fn reads_version_1() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&2u64.to_le_bytes());
+        for (url, computed) in [("a", true), ("b", false)] {
+            let json = serde_json::to_vec(&page(url, "old")).unwrap();
+            bytes.extend_from_slice(&(json.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&json);
+            if computed {
+                bytes.push(1);
+                bytes.extend_from_slice(&0.5f32.to_le_bytes());
+                bytes.extend_from_slice(&0.125f32.to_le_bytes());
+                for i in 0..768 {
+                    bytes.extend_from_slice(&(i as f32).to_le_bytes());
+                }
+            } else {
+                bytes.push(0);
+            }
+        }
+
+        let data = decode(&bytes).unwrap();
+        assert_eq!(data.length, 2);
+        assert_eq!(data.raw_text, vec![page("a", "old"), page("b", "old")]);
+        let computed = data.processed[0].as_ref().unwrap();
+        assert_eq!(computed.ai_description, 0.5);
+        assert_eq!(computed.ai_code, 0.125);
+        assert_eq!(computed.score_multiplier, 1.0);
+        assert_eq!(computed.model, embedder::LEGACY_MODEL);
+        assert!(!computed.outdated);
+        assert_eq!(computed.embedding[767], 767.0);
+        assert!(data.processed[1].is_none());
+        assert!(data.history.is_empty());
+        assert_eq!(data.scraped_at, vec![0, 0]);
+    }
+
+    // chains used to end with a copy of the current page
+    #[test]
+    fn splits_current_page_off_old_chains() {
+        let mut old = sample();
+        old.history
+            .get_mut(&UniqueString("a".into()))
+            .unwrap()
+            .push(Revision {
+                scraped_at: 20,
+                page: page("a", "second"),
+            });
+        old.history.insert(
+            UniqueString("b".into()),
+            vec![Revision {
+                scraped_at: 30,
+                page: page("b", "no embedding yet"),
+            }],
+        );
+        old.scraped_at.clear();
+        let bytes = encode(&old, StorageFormat::Json).unwrap();
+        assert_same(&sample(), &decode(&bytes).unwrap());
+    }
+
+    #[test]
+    fn rejects_newer_and_truncated_files() {
+        let mut bytes = encode(&sample(), StorageFormat::Binary).unwrap();
+        bytes[8..12].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(decode(&bytes).is_err());
+
+        let bytes = encode(&sample(), StorageFormat::Binary).unwrap();
+        assert!(decode(&bytes[..bytes.len() - 10]).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+};
+
+use crate::data::{ComputedData, ScrapedMainPageEnum, UniqueString};
+
+// append only log of writes since the last snapshot, one json object per line.
+// replayed on startup then truncated every time a full save lands on disk
+
+// records only live long enough to be written or replayed, boxing buys nothing
+#[allow(clippy::large_enum_variant)]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum WalRecord {
+    Add {
+        page: ScrapedMainPageEnum,
+        computed: Option<ComputedData>,
+        #[serde(default)]
+        scraped_at: u64,
+    },
+    // computed data replaced from /set_extras or the re-embed job, keyed like history
+    // so it cant land on the wrong entry
+    SetExtras {
+        key: UniqueString,
+        computed: ComputedData,
+    },
+}
+
+pub struct WriteAheadLog {
+    file: Option<File>,
+}
+
+// records that are written but maybe not on disk yet, see append
+pub struct PendingSync(Option<File>);
+
+impl PendingSync {
+    pub fn wait(self) -> io::Result<()> {
+        match self.0 {
+            Some(file) => file.sync_data(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl WriteAheadLog {
+    pub fn location(database: &str) -> String {
+        format!("{database}.wal")
+    }
+    // for in memory databases
+    pub fn disabled() -> WriteAheadLog {
+        WriteAheadLog { file: None }
+    }
+    // returns the records to replay, a torn last line from a crash mid append is
+    // cut off so new appends dont get glued onto it
+    pub fn open(database: &str) -> io::Result<(WriteAheadLog, Vec<WalRecord>)> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(Self::location(database))?;
+
+        let mut records = vec![];
+        let mut valid_length = 0;
+        let mut reader = BufReader::new(&mut file);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 {
+                break;
+            }
+            if !line.ends_with('\n') {
+                eprintln!("dropping torn wal record");
+                break;
+            }
+            if !line.trim().is_empty() {
+                match serde_json::from_str(&line) {
+                    Ok(record) => records.push(record),
+                    Err(e) => {
+                        eprintln!("stopping wal replay at unreadable record: {e}");
+                        break;
+                    }
+                }
+            }
+            valid_length += read as u64;
+        }
+
+        if valid_length < file.metadata()?.len() {
+            file.set_len(valid_length)?;
+            file.sync_all()?;
+        }
+        Ok((WriteAheadLog { file: Some(file) }, records))
+    }
+    // a whole batch goes out in one write, the fsync is left to the caller so it can
+    // happen after the database lock is let go. wait on it before telling anyone the
+    // write is saved
+    pub fn append(&mut self, records: &[WalRecord]) -> io::Result<PendingSync> {
+        let Some(file) = &mut self.file else {
+            return Ok(PendingSync(None));
+        };
+        if records.is_empty() {
+            return Ok(PendingSync(None));
+        }
+        let mut lines = vec![];
+        for record in records {
+            serde_json::to_writer(&mut lines, record).map_err(io::Error::other)?;
+            lines.push(b'\n');
+        }
+        file.write_all(&lines)?;
+        Ok(PendingSync(Some(file.try_clone()?)))
+    }
+    // only call once the snapshot holding these records is safely renamed into place
+    pub fn truncate(&mut self) -> io::Result<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        file.set_len(0)?;
+        file.sync_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{DatabasePage, Summer2025MainPage};
+    use std::fs;
+
+    fn page(url: &str) -> ScrapedMainPageEnum {
+        ScrapedMainPageEnum::Summer2025(Summer2025MainPage {
+            url: url.into(),
+            name: url.into(),
+            ..Default::default()
+        })
+    }
+
+    fn add(url: &str, scraped_at: u64) -> WalRecord {
+        WalRecord::Add {
+            page: page(url),
+            computed: None,
+            scraped_at,
+        }
+    }
+
+    // a fresh database name in the temp dir, wal and all
+    fn database(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("wal-test-{}-{name}", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let _ = fs::remove_file(WriteAheadLog::location(&path));
+        path
+    }
+
+    fn scraped(records: &[WalRecord]) -> Vec<u64> {
+        records
+            .iter()
+            .map(|record| match record {
+                WalRecord::Add { scraped_at, .. } => *scraped_at,
+                WalRecord::SetExtras { .. } => 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replays_what_was_appended() {
+        let name = database("replay");
+        let (mut wal, records) = WriteAheadLog::open(&name).unwrap();
+        assert!(records.is_empty());
+        let extras = WalRecord::SetExtras {
+            key: page("a").unique_string(),
+            computed: ComputedData::new([0.5; 768], "some-model"),
+        };
+        wal.append(&[add("a", 10), add("b", 20)])
+            .unwrap()
+            .wait()
+            .unwrap();
+        wal.append(&[extras]).unwrap().wait().unwrap();
+        drop(wal);
+
+        let (mut wal, records) = WriteAheadLog::open(&name).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(scraped(&records[..2]), vec![10, 20]);
+        match &records[2] {
+            WalRecord::SetExtras { key, computed } => {
+                assert_eq!(*key, page("a").unique_string());
+                assert_eq!(computed.model, "some-model");
+                assert_eq!(computed.embedding, [0.5; 768]);
+            }
+            other => panic!("expected extras, got {other:?}"),
+        }
+
+        // a save landed, nothing left to replay
+        wal.truncate().unwrap();
+        drop(wal);
+        assert!(WriteAheadLog::open(&name).unwrap().1.is_empty());
+        fs::remove_file(WriteAheadLog::location(&name)).unwrap();
+    }
+
+    #[test]
+    fn cuts_off_a_torn_last_line() {
+        let name = database("torn");
+        let location = WriteAheadLog::location(&name);
+        let (mut wal, _) = WriteAheadLog::open(&name).unwrap();
+        wal.append(&[add("a", 10)]).unwrap().wait().unwrap();
+        drop(wal);
+        let whole = fs::metadata(&location).unwrap().len();
+
+        // the crash came halfway through the next record
+        let mut torn = serde_json::to_vec(&add("b", 20)).unwrap();
+        torn.truncate(torn.len() / 2);
+        OpenOptions::new()
+            .append(true)
+            .open(&location)
+            .unwrap()
+            .write_all(&torn)
+            .unwrap();
+
+        let (mut wal, records) = WriteAheadLog::open(&name).unwrap();
+        assert_eq!(scraped(&records), vec![10]);
+        assert_eq!(fs::metadata(&location).unwrap().len(), whole);
+
+        // new appends start on a line of their own
+        wal.append(&[add("c", 30)]).unwrap().wait().unwrap();
+        drop(wal);
+        let (_, records) = WriteAheadLog::open(&name).unwrap();
+        assert_eq!(scraped(&records), vec![10, 30]);
+        fs::remove_file(location).unwrap();
+    }
+}
@@ -0,0 +1,31 @@
+# https://journey.hackclub.com/gallery
+# same rails app summer grew out of, so most of the page layout carries over
+import json
+from typing import Annotated, List, Optional
+from library import *
+from browser import ScraperBrowser
+from library import Text, Link
+
+class IndividualUpdateJourney:
+    # kept as the displayed text, the backend stores journey times as strings
+    time:           Annotated[Text, './/div[@class="text-som-detail"]/span[1]']
+    message:        Annotated[Text, './/div[@data-devlog-card-target="content"]']
+    attatchments:   Annotated[List[Link], './/img[contains(@class, "max-h-96")]']
+
+class Journey2025:
+    main_image:     Annotated[Link, '//div[contains(@class, "h-48")]//img']
+    name:           Annotated[Text, '//h1']
+    description:    Annotated[Text, '//div[contains(@class, "[p]:text-inherit")]/p']
+    author:         Annotated[Text, '//span[contains(text(), "Created by")]/span/a']
+    followers:      Annotated[int, '//button[@data-modal-type="follower"]/span']
+    stonks:         Annotated[int, '//button[@data-modal-type="stonks"]/span']
+    time:           Annotated[Text, '(//span[@class="text-som-dark"])[3]']
+    readme:         Annotated[Optional[Link], '//button[@data-modal-type="readme"]']
+    repo:           Annotated[Optional[Link], '//a[contains(., "Repository")]']
+    demo:           Annotated[Optional[Link], '//a[contains(., "Demo")]']
+
+    updates:        Annotated[List[IndividualUpdateJourney], '//div[@data-controller="modal devlog-card"]']
+
+
+def run():
+    return Journey2025
@@ -9,24 +9,34 @@ import concurrent.futures
 import traceback
 import requests
 import os
+import sys
 
 NUM_THREADS = 6
-MAX_ID = 16000
+
+# event name -> (project url, highest project id to try)
+EVENTS = {
+    "summer": ("https://summer.hackclub.com/projects/{}", 16000),
+    "journey": ("https://journey.hackclub.com/projects/{}", 4000),
+}
 
 r = requests.Session()
 
 
-def worker(start_inclusive: int, end_exclusive: int, thread_idx: int):
-    print(f"[T{thread_idx}] starting: {start_inclusive}..{end_exclusive - 1}")
+def worker(event: str, start_inclusive: int, end_exclusive: int, thread_idx: int):
+    print(f"[T{thread_idx}] starting {event}: {start_inclusive}..{end_exclusive - 1}")
     try:
-        scraper_factory = dyn_import_scraper("summer", 2025)
+        scraper_factory = dyn_import_scraper(event, 2025)
+        url_template, _ = EVENTS[event]
 
         with ScraperBrowser(simple=True) as browser:
             for i in range(start_inclusive, end_exclusive):
                 try:
-                    url = f"https://summer.hackclub.com/projects/{i}"
+                    url = url_template.format(i)
                     browser.nagivate_to(url)
                     result = browser.parse(scraper_factory())
+                    # journey pages dont show their own id, the backend keys on it
+                    if event == "journey":
+                        result._set_kv("id", i)
 
                     image_url = result.main_image
                     if not image_url:
@@ -38,7 +48,7 @@ def worker(start_inclusive: int, end_exclusive: int, thread_idx: int):
 
                     # istg theres gifs but this doesnt capture them
                     ext = os.path.splitext(img_response.url.split("?")[0])[1] or ".jpg"
-                    filename = f"project_{i}{ext}"
+                    filename = f"{event}_project_{i}{ext}"
 
                     upload_url = f"{BACKEND_URL}/upload_image"
                     upload_resp = r.post(
@@ -59,7 +69,8 @@ def worker(start_inclusive: int, end_exclusive: int, thread_idx: int):
                             print(f"[T{thread_idx}] image upload again failed for {i}: {upload_resp.text}")
                             continue
 
-                    new_url = f"{BACKEND_URL}/images/{filename}"
+                    # stored under a hash of the bytes, the backend says where
+                    new_url = f"{BACKEND_URL}{upload_resp.json()['url']}"
                     result._set_kv("main_image", new_url)
 
                     scraped_data_dict = {result.__name__: result.json()}
@@ -94,12 +105,17 @@ if __name__ == "__main__":
     BACKEND_URL = "http://searxing.hackclub.app"
     AUTH_SECRET = "not_a_secret_secret"
 
-    ranges = chunk_ranges(MAX_ID, NUM_THREADS)
+    event = sys.argv[1] if len(sys.argv) > 1 else "summer"
+    if event not in EVENTS:
+        raise SystemExit(f"unknown event {event}, pick one of {', '.join(EVENTS)}")
+    _, max_id = EVENTS[event]
+
+    ranges = chunk_ranges(max_id, NUM_THREADS)
 
     with concurrent.futures.ThreadPoolExecutor(max_workers=NUM_THREADS) as ex:
         futures = []
         for idx, (s, e) in enumerate(ranges):
-            futures.append(ex.submit(worker, s, e, idx))
+            futures.append(ex.submit(worker, event, s, e, idx))
         for fut in concurrent.futures.as_completed(futures):
             try:
                 fut.result()